@@ -0,0 +1,289 @@
+use glam::{Quat, Vec3};
+use std::path::Path;
+
+use crate::error::LoadError;
+use crate::skinning::{NodeTree, Skin};
+use crate::utils::import_gltf_buffers;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    // every key has an in tangent, the value and an out tangent
+    CubicSpline,
+}
+
+// Part of the node transform a channel writes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Property {
+    Translation,
+    Rotation,
+    Scale,
+    // morph target weights of the mesh of the node
+    Weights,
+}
+
+// Keyframes of one property, the values of all keys one after the other
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    pub times: Vec<f32>,
+    pub values: Vec<f32>,
+    pub components: usize,
+    pub interpolation: Interpolation,
+}
+
+impl Sampler {
+    fn stride(&self) -> usize {
+        match self.interpolation {
+            Interpolation::CubicSpline => self.components * 3,
+            _ => self.components,
+        }
+    }
+
+    // value of key `key`, `part` picks the in tangent (0), value (1) or out tangent (2) of cubic splines
+    fn key(&self, key: usize, part: usize) -> &[f32] {
+        let start = key * self.stride() + if self.interpolation == Interpolation::CubicSpline { part * self.components } else { 0 };
+        &self.values[start..start + self.components]
+    }
+
+    // Value at `time`, held at the first and last keys outside of them.
+    // Rotations are unit quaternions in x, y, z, w order and are interpolated along the sphere.
+    pub fn sample(&self, time: f32, rotation: bool) -> Vec<f32> {
+        if self.times.is_empty() {
+            return vec![0.0; self.components];
+        }
+        let last = self.times.len() - 1;
+        let next = self.times.partition_point(|t| *t <= time);
+        if next == 0 {
+            return self.key(0, 1).to_vec();
+        }
+        if next > last {
+            return self.key(last, 1).to_vec();
+        }
+        let previous = next - 1;
+        let delta = self.times[next] - self.times[previous];
+        let t = if delta > 0.0 { (time - self.times[previous]) / delta } else { 0.0 };
+
+        match self.interpolation {
+            Interpolation::Step => self.key(previous, 1).to_vec(),
+            Interpolation::Linear if rotation => {
+                let (a, b) = (self.key(previous, 1), self.key(next, 1));
+                let a = Quat::from_xyzw(a[0], a[1], a[2], a[3]);
+                let b = Quat::from_xyzw(b[0], b[1], b[2], b[3]);
+                a.slerp(b, t).to_array().to_vec()
+            }
+            Interpolation::Linear => {
+                let (a, b) = (self.key(previous, 1), self.key(next, 1));
+                a.iter().zip(b).map(|(a, b)| a + (b - a) * t).collect()
+            }
+            Interpolation::CubicSpline => {
+                // Hermite spline, the tangents are scaled by the duration between the keys
+                let (t2, t3) = (t * t, t * t * t);
+                let p0 = self.key(previous, 1);
+                let m0 = self.key(previous, 2);
+                let p1 = self.key(next, 1);
+                let m1 = self.key(next, 0);
+                let mut value: Vec<f32> = (0..self.components)
+                    .map(|i| {
+                        (2.0 * t3 - 3.0 * t2 + 1.0) * p0[i]
+                            + (t3 - 2.0 * t2 + t) * delta * m0[i]
+                            + (-2.0 * t3 + 3.0 * t2) * p1[i]
+                            + (t3 - t2) * delta * m1[i]
+                    })
+                    .collect();
+                if rotation {
+                    let length = value.iter().map(|v| v * v).sum::<f32>().sqrt();
+                    if length > 0.0 {
+                        value.iter_mut().for_each(|v| *v /= length);
+                    }
+                }
+                value
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub node: usize,
+    pub property: Property,
+    pub sampler: Sampler,
+}
+
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub name: String,
+    pub channels: Vec<Channel>,
+    // time of the last key of all channels
+    pub duration: f32,
+}
+
+impl Animation {
+    // a channel without values for its keys is an error
+    pub fn from_gltf(animation: &gltf::Animation, buffers: &[gltf::buffer::Data]) -> Result<Self, LoadError> {
+        let mut channels = Vec::new();
+        for channel in animation.channels() {
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let Some(times) = reader.read_inputs() else {
+                continue;
+            };
+            let times: Vec<f32> = times.collect();
+            let (property, values): (Property, Vec<f32>) = match reader.read_outputs() {
+                Some(gltf::animation::util::ReadOutputs::MorphTargetWeights(values)) => {
+                    (Property::Weights, values.into_f32().collect())
+                }
+                Some(gltf::animation::util::ReadOutputs::Translations(values)) => {
+                    (Property::Translation, values.flatten().collect())
+                }
+                Some(gltf::animation::util::ReadOutputs::Rotations(values)) => {
+                    (Property::Rotation, values.into_f32().flatten().collect())
+                }
+                Some(gltf::animation::util::ReadOutputs::Scales(values)) => (Property::Scale, values.flatten().collect()),
+                None => {
+                    let property = channel.target().property();
+                    return Err(LoadError::Invalid(format!("animation channel of {:?} has no values", property)));
+                }
+            };
+            let interpolation = match channel.sampler().interpolation() {
+                gltf::animation::Interpolation::Step => Interpolation::Step,
+                gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+            };
+            let keys = times.len() * if interpolation == Interpolation::CubicSpline { 3 } else { 1 };
+            let components = match property {
+                Property::Rotation => 4,
+                Property::Weights => values.len() / keys.max(1),
+                _ => 3,
+            };
+            let sampler = Sampler {
+                times,
+                values,
+                components,
+                interpolation,
+            };
+            if sampler.values.len() < sampler.times.len() * sampler.stride() {
+                return Err(LoadError::Invalid("animation channel has fewer values than keys".to_string()));
+            }
+            channels.push(Channel {
+                node: channel.target().node().index(),
+                property,
+                sampler,
+            });
+        }
+        let duration = channels
+            .iter()
+            .filter_map(|c| c.sampler.times.last())
+            .fold(0.0f32, |duration, t| duration.max(*t));
+        Ok(Self {
+            name: animation.name().unwrap_or_default().to_string(),
+            channels,
+            duration,
+        })
+    }
+
+    // writes the pose at `time` into the transforms of the animated nodes
+    pub fn apply(&self, time: f32, nodes: &mut NodeTree) {
+        for channel in &self.channels {
+            let Some(transform) = nodes.transforms.get_mut(channel.node) else {
+                continue;
+            };
+            let value = channel.sampler.sample(time, channel.property == Property::Rotation);
+            match channel.property {
+                Property::Translation => transform.translation = Vec3::from_slice(&value),
+                Property::Rotation => transform.rotation = Quat::from_slice(&value).normalize(),
+                Property::Scale => transform.scale = Vec3::from_slice(&value),
+                Property::Weights => nodes.weights[channel.node] = value,
+            }
+        }
+    }
+}
+
+// Position of the viewer in an animation
+#[derive(Debug, Copy, Clone)]
+pub struct Playback {
+    pub time: f32,
+    pub speed: f32,
+    pub playing: bool,
+    pub looping: bool,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self {
+            time: 0.0,
+            speed: 1.0,
+            playing: true,
+            looping: true,
+        }
+    }
+}
+
+impl Playback {
+    pub fn advance(&mut self, dt: f32, duration: f32) {
+        if self.playing {
+            self.seek(self.time + dt * self.speed, duration);
+        }
+    }
+
+    // jumps to `time`, wrapped around when looping and clamped otherwise
+    pub fn seek(&mut self, time: f32, duration: f32) {
+        self.time = if duration <= 0.0 {
+            0.0
+        } else if self.looping {
+            time.rem_euclid(duration)
+        } else {
+            time.clamp(0.0, duration)
+        };
+    }
+}
+
+// Nodes, skin and animations of the mesh node a glTF document is shown with
+#[derive(Debug, Clone)]
+pub struct Rig {
+    pub nodes: NodeTree,
+    pub skin: Option<Skin>,
+    pub animations: Vec<Animation>,
+    // node of the mesh
+    pub node: usize,
+}
+
+impl Rig {
+    pub fn from_gltf(document: &gltf::Document, node: &gltf::Node, buffers: &[gltf::buffer::Data]) -> Result<Self, LoadError> {
+        Ok(Self {
+            nodes: NodeTree::from_gltf(document),
+            skin: node.skin().map(|skin| Skin::from_gltf(&skin, buffers)),
+            animations: document.animations().map(|a| Animation::from_gltf(&a, buffers)).collect::<Result<_, _>>()?,
+            node: node.index(),
+        })
+    }
+
+    // weights of the morph targets of the mesh node, none when it has none
+    pub fn morph_weights(&self) -> Option<&[f32]> {
+        Some(self.nodes.weights[self.node].as_slice()).filter(|weights| !weights.is_empty())
+    }
+
+    // poses the nodes as `animation` is at `time`, the nodes it does not animate are at rest
+    pub fn pose(&mut self, animation: usize, time: f32) {
+        self.nodes.reset();
+        if let Some(animation) = self.animations.get(animation) {
+            animation.apply(time, &mut self.nodes);
+        }
+    }
+}
+
+// The rig of the node the viewer shows, none for other formats or when there is nothing to animate.
+// Only the buffers are loaded, the textures come with the mesh.
+pub fn load_rig(path: &Path) -> Result<Option<Rig>, LoadError> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    if !matches!(extension.as_str(), "gltf" | "glb") {
+        return Ok(None);
+    }
+    let (document, buffers) = import_gltf_buffers(path)?;
+    let Some(node) = document.scenes().flat_map(|scene| scene.nodes()).find(|node| node.mesh().is_some()) else {
+        return Ok(None);
+    };
+    if node.skin().is_none() && document.animations().next().is_none() {
+        return Ok(None);
+    }
+    Rig::from_gltf(&document, &node, buffers.as_slice()).map(Some)
+}
@@ -0,0 +1,91 @@
+use glam::Vec2;
+
+use crate::utils::{from_argb8_v, to_argb8_v};
+
+// sample counts with a pattern, the per-sample masks of the rasterizer hold at most 8
+pub const SUPPORTED_SAMPLES: [usize; 4] = [1, 2, 4, 8];
+
+// the biggest supported sample count that is not above `samples`, at least 1
+pub fn supported_samples(samples: usize) -> usize {
+    SUPPORTED_SAMPLES.iter().copied().filter(|s| *s <= samples).max().unwrap_or(1)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AntiAliasing {
+    Off,
+    // coverage and depth per sample, shading once per pixel, see `supported_samples`
+    Msaa(usize),
+    // the whole frame is rendered `factor` times bigger in both directions and downsampled
+    Ssaa(usize),
+}
+
+impl AntiAliasing {
+    pub fn samples(&self) -> usize {
+        match self {
+            AntiAliasing::Msaa(samples) => supported_samples(*samples),
+            _ => 1,
+        }
+    }
+
+    pub fn scale(&self) -> usize {
+        match self {
+            AntiAliasing::Ssaa(factor) => *factor,
+            _ => 1,
+        }
+    }
+}
+
+// Standard D3D sample positions, in 1/16 of a pixel relative to the pixel center
+const PATTERN_1X: [(f32, f32); 1] = [(0.0, 0.0)];
+const PATTERN_2X: [(f32, f32); 2] = [(4.0, 4.0), (-4.0, -4.0)];
+const PATTERN_4X: [(f32, f32); 4] = [(-2.0, -6.0), (6.0, -2.0), (-6.0, 2.0), (2.0, 6.0)];
+const PATTERN_8X: [(f32, f32); 8] = [
+    (1.0, -3.0),
+    (-1.0, 3.0),
+    (5.0, 1.0),
+    (-3.0, -5.0),
+    (-5.0, 5.0),
+    (-7.0, -1.0),
+    (3.0, 7.0),
+    (7.0, -7.0),
+];
+
+// pattern of `supported_samples(samples)` samples
+pub fn sample_pattern(samples: usize) -> Vec<Vec2> {
+    let pattern: &[(f32, f32)] = match supported_samples(samples) {
+        1 => &PATTERN_1X,
+        2 => &PATTERN_2X,
+        4 => &PATTERN_4X,
+        _ => &PATTERN_8X,
+    };
+    pattern
+        .iter()
+        .map(|(x, y)| glam::vec2(*x, *y) / 16.0)
+        .collect()
+}
+
+// averages `samples` consecutive values of `src` into every pixel of `dst`
+pub fn resolve_samples(src: &[u32], samples: usize, dst: &mut [u32]) {
+    for (index, pixel) in dst.iter_mut().enumerate() {
+        let sum = src[index * samples..(index + 1) * samples]
+            .iter()
+            .fold(glam::Vec3::ZERO, |sum, c| sum + from_argb8_v(*c));
+        *pixel = to_argb8_v(255, sum / samples as f32);
+    }
+}
+
+// box filter of `factor` x `factor` blocks of `src` into `dst`
+pub fn downsample(src: &[u32], src_width: usize, factor: usize, dst: &mut [u32], dst_width: usize) {
+    let weight = 1.0 / (factor * factor) as f32;
+    for (index, pixel) in dst.iter_mut().enumerate() {
+        let (x, y) = (index % dst_width, index / dst_width);
+        let mut sum = glam::Vec3::ZERO;
+        for sy in 0..factor {
+            let row = (y * factor + sy) * src_width + x * factor;
+            for c in &src[row..row + factor] {
+                sum += from_argb8_v(*c);
+            }
+        }
+        *pixel = to_argb8_v(255, sum * weight);
+    }
+}
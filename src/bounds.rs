@@ -0,0 +1,177 @@
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
+
+// Axis aligned bounding box, empty when `min` is greater than `max`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+impl Aabb {
+    pub const EMPTY: Self = Self {
+        min: Vec3::splat(f32::INFINITY),
+        max: Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        points.into_iter().fold(Self::EMPTY, |aabb, p| aabb.extend(p))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpgt(self.max).any()
+    }
+
+    pub fn extend(self, point: Vec3) -> Self {
+        Self {
+            min: self.min.min(point),
+            max: self.max.max(point),
+        }
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) / 2.0
+    }
+
+    pub fn half_extents(&self) -> Vec3 {
+        (self.max - self.min) / 2.0
+    }
+
+    // box around the transformed box, by Jim Arvo
+    pub fn transform(&self, matrix: &Mat4) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        let center = matrix.transform_point3(self.center());
+        let extents = self.half_extents();
+        let half = matrix.x_axis.xyz().abs() * extents.x
+            + matrix.y_axis.xyz().abs() * extents.y
+            + matrix.z_axis.xyz().abs() * extents.z;
+        Self {
+            min: center - half,
+            max: center + half,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    // centered on the box around the points, not the smallest sphere but close enough for culling
+    pub fn from_points(points: impl IntoIterator<Item = Vec3> + Clone) -> Self {
+        let aabb = Aabb::from_points(points.clone());
+        if aabb.is_empty() {
+            return Self::default();
+        }
+        let center = aabb.center();
+        let radius = points.into_iter().fold(0.0f32, |r, p| r.max(p.distance(center)));
+        Self { center, radius }
+    }
+
+    // a non-uniform scale grows the sphere by its largest factor
+    pub fn transform(&self, matrix: &Mat4) -> Self {
+        let scale = matrix.x_axis.xyz().length().max(matrix.y_axis.xyz().length()).max(matrix.z_axis.xyz().length());
+        Self {
+            center: matrix.transform_point3(self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+// Planes of the clip space volume in the space before `matrix`, pointing inwards.
+// Extracted from the rows of the matrix, by Gribb and Hartmann.
+#[derive(Debug, Copy, Clone)]
+pub struct Frustum {
+    pub planes: [Vec4; 6],
+}
+
+impl Frustum {
+    // `matrix` is usually `projection * view`, giving the planes in world space.
+    // Depth goes from 0 to w like the projections of `Camera`, an infinite far plane
+    // becomes a plane that everything is inside of.
+    pub fn from_matrix(matrix: &Mat4) -> Self {
+        let row = |i: usize| matrix.row(i);
+        let planes = [
+            row(3) + row(0), // left
+            row(3) - row(0), // right
+            row(3) + row(1), // bottom
+            row(3) - row(1), // top
+            row(2),          // near, or far with reverse-Z
+            row(3) - row(2), // far, or near with reverse-Z
+        ]
+        .map(|plane| {
+            let length = plane.xyz().length();
+            if length > 0.0 { plane / length } else { plane }
+        });
+        Self { planes }
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.xyz().dot(sphere.center) + plane.w >= -sphere.radius)
+    }
+
+    // only the corner farthest along each plane normal has to be tested
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        if aabb.is_empty() {
+            return false;
+        }
+        self.planes.iter().all(|plane| {
+            let normal = plane.xyz();
+            let farthest = Vec3::select(normal.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            normal.dot(farthest) + plane.w >= 0.0
+        })
+    }
+}
+
+// Half line from `origin`, `direction` does not have to be normalized
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Self { origin, direction }
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.direction * t
+    }
+
+    // keeps `t` meaning the same point, the direction is not normalized again
+    pub fn transform(&self, matrix: &Mat4) -> Self {
+        Self {
+            origin: matrix.transform_point3(self.origin),
+            direction: matrix.transform_vector3(self.direction),
+        }
+    }
+
+    // distance along the ray at which it enters the box, the slab test
+    pub fn intersect_aabb(&self, aabb: &Aabb, max_t: f32) -> Option<f32> {
+        let inverse = self.direction.recip();
+        let t0 = (aabb.min - self.origin) * inverse;
+        let t1 = (aabb.max - self.origin) * inverse;
+        let near = t0.min(t1).max_element().max(0.0);
+        let far = t0.max(t1).min_element().min(max_t);
+        (near <= far).then_some(near)
+    }
+}
@@ -0,0 +1,220 @@
+use glam::{Vec2, Vec3};
+
+use crate::bounds::{Aabb, Ray};
+use crate::geometry::Mesh;
+
+// number of buckets the centroids are sorted into when looking for the best split
+const SAH_BINS: usize = 12;
+// traversing a node costs about as much as this many triangle tests
+const TRAVERSAL_COST: f32 = 1.0;
+
+#[derive(Debug, Copy, Clone)]
+struct BvhNode {
+    aabb: Aabb,
+    // leaves reference `count` triangles from `first` on, inner nodes (count 0)
+    // have their children at `first` and `first + 1`
+    first: u32,
+    count: u32,
+}
+
+// Closest intersection of a ray with a mesh
+#[derive(Debug, Copy, Clone)]
+pub struct Hit {
+    pub t: f32,
+    pub triangle: usize,
+    // weights of the three vertices of the triangle
+    pub barycentric: Vec3,
+    // in the space of the mesh
+    pub position: Vec3,
+    pub uv: Vec2,
+}
+
+// Bounding volume hierarchy over the triangles of a mesh, built with the surface area heuristic
+#[derive(Debug, Clone)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    // triangle indices of the mesh, in the order the leaves reference them
+    triangles: Vec<u32>,
+}
+
+fn surface_area(aabb: &Aabb) -> f32 {
+    if aabb.is_empty() {
+        return 0.0;
+    }
+    let size = aabb.max - aabb.min;
+    2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+}
+
+impl Bvh {
+    pub fn build(mesh: &Mesh) -> Self {
+        let bounds: Vec<Aabb> = mesh
+            .triangles()
+            .iter()
+            .map(|t| Aabb::from_points(mesh.get_vertices_from_triangle(*t).map(|v| v.pos)))
+            .collect();
+        let centroids: Vec<Vec3> = bounds.iter().map(Aabb::center).collect();
+
+        let mut bvh = Self {
+            nodes: vec![BvhNode {
+                aabb: bounds.iter().fold(Aabb::EMPTY, |aabb, b| aabb.union(*b)),
+                first: 0,
+                count: bounds.len() as u32,
+            }],
+            triangles: (0..bounds.len() as u32).collect(),
+        };
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            if let Some((left, right)) = bvh.split(node, &bounds, &centroids) {
+                stack.extend([left, right]);
+            }
+        }
+        bvh
+    }
+
+    // splits the leaf `node` where the SAH cost is lowest, if that is cheaper than keeping it
+    fn split(&mut self, node: usize, bounds: &[Aabb], centroids: &[Vec3]) -> Option<(usize, usize)> {
+        let BvhNode { aabb, first, count } = self.nodes[node];
+        if count <= 2 {
+            return None;
+        }
+        let range = first as usize..(first + count) as usize;
+        let centroid_bounds = Aabb::from_points(self.triangles[range.clone()].iter().map(|t| centroids[*t as usize]));
+        let extent = centroid_bounds.max - centroid_bounds.min;
+        let bin_of = |axis: usize, t: u32| {
+            let offset = (centroids[t as usize][axis] - centroid_bounds.min[axis]) / extent[axis];
+            ((offset * SAH_BINS as f32) as usize).min(SAH_BINS - 1)
+        };
+
+        // (cost, axis, first bin of the right side)
+        let mut best: Option<(f32, usize, usize)> = None;
+        for axis in 0..3 {
+            if extent[axis] <= 0.0 {
+                continue;
+            }
+            let mut bins = [(Aabb::EMPTY, 0u32); SAH_BINS];
+            for t in &self.triangles[range.clone()] {
+                let bin = &mut bins[bin_of(axis, *t)];
+                bin.0 = bin.0.union(bounds[*t as usize]);
+                bin.1 += 1;
+            }
+            // areas and counts of everything left of each plane, then right of it
+            let mut left = [(0.0, 0); SAH_BINS];
+            let (mut aabb, mut count) = (Aabb::EMPTY, 0);
+            for i in 0..SAH_BINS - 1 {
+                aabb = aabb.union(bins[i].0);
+                count += bins[i].1;
+                left[i] = (surface_area(&aabb), count);
+            }
+            let (mut aabb, mut count) = (Aabb::EMPTY, 0);
+            for plane in (1..SAH_BINS).rev() {
+                aabb = aabb.union(bins[plane].0);
+                count += bins[plane].1;
+                let (left_area, left_count) = left[plane - 1];
+                if left_count == 0 || count == 0 {
+                    continue;
+                }
+                let cost = left_area * left_count as f32 + surface_area(&aabb) * count as f32;
+                if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, axis, plane));
+                }
+            }
+        }
+
+        let (cost, axis, plane) = best?;
+        let leaf_cost = surface_area(&aabb) * count as f32;
+        if TRAVERSAL_COST * surface_area(&aabb) + cost >= leaf_cost {
+            return None;
+        }
+
+        // partition the triangles of the node around the plane
+        let triangles = &mut self.triangles[range.clone()];
+        let mut left_count = 0;
+        for i in 0..triangles.len() {
+            if bin_of(axis, triangles[i]) < plane {
+                triangles.swap(i, left_count);
+                left_count += 1;
+            }
+        }
+
+        let children = self.nodes.len();
+        let halves = [(first, left_count as u32), (first + left_count as u32, count - left_count as u32)];
+        for (first, count) in halves {
+            let aabb = self.triangles[first as usize..(first + count) as usize]
+                .iter()
+                .fold(Aabb::EMPTY, |aabb, t| aabb.union(bounds[*t as usize]));
+            self.nodes.push(BvhNode { aabb, first, count });
+        }
+        self.nodes[node] = BvhNode { aabb, first: children as u32, count: 0 };
+        Some((children, children + 1))
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        self.nodes[0].aabb
+    }
+
+    // closest triangle hit by `ray`, given in the space of the mesh the hierarchy was built for
+    pub fn intersect(&self, mesh: &Mesh, ray: &Ray) -> Option<Hit> {
+        let mut closest: Option<Hit> = None;
+        let mut stack = vec![0usize];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let max_t = closest.map_or(f32::INFINITY, |hit| hit.t);
+            if ray.intersect_aabb(&node.aabb, max_t).is_none() {
+                continue;
+            }
+            if node.count > 0 {
+                for t in &self.triangles[node.first as usize..(node.first + node.count) as usize] {
+                    if let Some(hit) = intersect_triangle(mesh, *t as usize, ray) {
+                        if closest.is_none_or(|c| hit.t < c.t) {
+                            closest = Some(hit);
+                        }
+                    }
+                }
+                continue;
+            }
+            // the nearer child is visited first, so the farther one is more likely to be skipped
+            let (left, right) = (node.first as usize, node.first as usize + 1);
+            let distance = |child: usize| ray.intersect_aabb(&self.nodes[child].aabb, max_t).unwrap_or(f32::INFINITY);
+            if distance(left) <= distance(right) {
+                stack.extend([right, left]);
+            } else {
+                stack.extend([left, right]);
+            }
+        }
+        closest
+    }
+}
+
+// Möller–Trumbore, both sides of the triangle are hit
+pub fn intersect_triangle(mesh: &Mesh, triangle: usize, ray: &Ray) -> Option<Hit> {
+    let [v0, v1, v2] = mesh.get_vertices_from_triangle(mesh.triangles()[triangle]);
+    let (edge1, edge2) = (v1.pos - v0.pos, v2.pos - v0.pos);
+    let p = ray.direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < f32::EPSILON * edge1.length() * edge2.length() * ray.direction.length() {
+        return None;
+    }
+    let inverse = 1.0 / determinant;
+    let s = ray.origin - v0.pos;
+    let u = s.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = ray.direction.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = edge2.dot(q) * inverse;
+    if t < 0.0 {
+        return None;
+    }
+    let barycentric = glam::vec3(1.0 - u - v, u, v);
+    Some(Hit {
+        t,
+        triangle,
+        barycentric,
+        position: ray.at(t),
+        uv: v0.uv * barycentric.x + v1.uv * barycentric.y + v2.uv * barycentric.z,
+    })
+}
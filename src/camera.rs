@@ -1,6 +1,7 @@
+use crate::bounds::Ray;
 use crate::transform::Transform;
 
-use glam::Mat4;
+use glam::{Mat4, Vec4Swizzles};
 
 pub struct Camera {
     pub frustum_near: f32,
@@ -9,7 +10,13 @@ pub struct Camera {
     pub aspect_ratio: f32,
     pub transform: Transform,
     pub speed: f32,
-    pub sens: f32
+    pub sens: f32,
+    // depth of field
+    pub focus_distance: f32,
+    pub aperture: f32, // diameter of the lens, 0.0 keeps everything in focus
+    // infinite far plane with depth going from 1.0 at the near plane to 0.0 at infinity,
+    // `frustum_far` is ignored by the projection
+    pub reverse_z: bool,
 }
 
 impl Default for Camera {
@@ -21,13 +28,23 @@ impl Default for Camera {
             aspect_ratio: 1.0,
             transform: Transform::IDENTITY,
             speed: 1.0,
-            sens: 1.5
+            sens: 1.5,
+            focus_distance: 3.0,
+            aperture: 0.05,
+            reverse_z: false,
         }
     }
 }
 
 impl Camera {
     pub fn projection(&self) -> Mat4 {
+        if self.reverse_z {
+            return Mat4::perspective_infinite_reverse_rh(
+                self.fov,
+                self.aspect_ratio,
+                self.frustum_near,
+            );
+        }
         Mat4::perspective_rh(
             self.fov,
             self.aspect_ratio,
@@ -36,6 +53,26 @@ impl Camera {
         )
     }
 
+    // distance along the view direction of a depth buffer value
+    pub fn linearize_depth(&self, depth: f32) -> f32 {
+        let near = self.frustum_near;
+        let far = self.frustum_far;
+        if self.reverse_z {
+            return near / depth.max(f32::EPSILON);
+        }
+        near * far / (far - depth * (far - near))
+    }
+
+    // world space ray through the center of the pixel at `x`, `y` of a `width` x `height` window,
+    // starting at the camera
+    pub fn pixel_ray(&self, x: f32, y: f32, width: f32, height: f32) -> Ray {
+        let ndc = glam::vec2((x + 0.5) / width * 2.0 - 1.0, 1.0 - (y + 0.5) / height * 2.0);
+        // any depth inside the frustum works, this one is finite with reverse-Z too
+        let point = (self.projection() * self.view()).inverse() * glam::vec4(ndc.x, ndc.y, 0.5, 1.0);
+        let origin = self.transform.translation;
+        Ray::new(origin, (point.xyz() / point.w - origin).normalize())
+    }
+
     pub fn view(&self) -> Mat4 {
         Mat4::look_at_rh(
             self.transform.translation,
@@ -0,0 +1,207 @@
+use glam::{Mat3, Vec2, Vec3};
+
+use crate::camera::Camera;
+use crate::framebuffer::Framebuffer;
+use crate::geometry::Vertex;
+use crate::texture::Texture;
+use crate::utils::{from_argb8_v, to_argb8_v};
+
+// fragments drawn over a pixel at which the heat map turns red
+pub const MAX_OVERDRAW: u32 = 8;
+// tiles per unit of the UV checker
+const CHECKER_TILES: f32 = 8.0;
+
+// What the debug view shows instead of the shaded image, the viewer cycles through them
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DebugMode {
+    // distance from the camera read back from the depth buffer, near is bright
+    Depth,
+    WorldNormals,
+    ViewNormals,
+    UvChecker,
+    UvCoordinates,
+    VertexColors,
+    // fragments shaded per pixel, blue once to red at `MAX_OVERDRAW`
+    Overdraw,
+    // a color for every triangle of a mesh
+    TriangleIds,
+    // the level a mip-mapped lookup of the texture would read, the texture has only the first one
+    MipLevel,
+}
+
+impl DebugMode {
+    pub const ALL: [DebugMode; 9] = [
+        DebugMode::Depth,
+        DebugMode::WorldNormals,
+        DebugMode::ViewNormals,
+        DebugMode::UvChecker,
+        DebugMode::UvCoordinates,
+        DebugMode::VertexColors,
+        DebugMode::Overdraw,
+        DebugMode::TriangleIds,
+        DebugMode::MipLevel,
+    ];
+
+    // the mode after `mode`, none after the last one and the first one after none
+    pub fn next(mode: Option<Self>) -> Option<Self> {
+        match mode {
+            None => Some(Self::ALL[0]),
+            Some(mode) => Self::ALL.iter().skip_while(|m| **m != mode).nth(1).copied(),
+        }
+    }
+}
+
+// Alternate fragment path of a render target, fragments get a debug color instead of being
+// textured and lit. Depth and overdraw are only known once every draw is done and replace
+// the colors in `resolve_debug_view`.
+#[derive(Debug, Clone)]
+pub struct DebugView {
+    pub mode: DebugMode,
+    // rotation of world space normals into view space
+    pub view: Mat3,
+    // index of the triangle being drawn in its mesh
+    pub triangle: usize,
+    // fragments shaded per pixel since the last clear
+    pub overdraw: Vec<u32>,
+}
+
+impl DebugView {
+    pub fn new(mode: DebugMode, camera: &Camera, width: usize, height: usize) -> Self {
+        Self {
+            mode,
+            view: Mat3::from_mat4(camera.view()),
+            triangle: 0,
+            overdraw: vec![0; width * height],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.overdraw.iter_mut().for_each(|count| *count = 0);
+    }
+
+    // counts a fragment of pixel `index` that is not shaded by `shade`, lines and points
+    pub fn count(&mut self, index: usize) {
+        self.overdraw[index] += 1;
+    }
+
+    // Color of a fragment of pixel `index`. `vertex` holds the perspective correct attributes
+    // at the pixel, `uv_dx` and `uv_dy` how the UV changes to the next pixel right and down.
+    pub fn shade(&mut self, index: usize, vertex: &Vertex, uv_dx: Vec2, uv_dy: Vec2, texture: &Texture) -> u32 {
+        self.count(index);
+        let color = match self.mode {
+            // replaced when resolved
+            DebugMode::Depth | DebugMode::Overdraw => Vec3::ZERO,
+            DebugMode::WorldNormals => vertex.normal.normalize_or_zero() * 0.5 + 0.5,
+            DebugMode::ViewNormals => (self.view * vertex.normal).normalize_or_zero() * 0.5 + 0.5,
+            DebugMode::UvChecker => {
+                let cell = (vertex.uv * CHECKER_TILES).floor();
+                let odd = (cell.x + cell.y).rem_euclid(2.0) == 1.0;
+                // tinted by the UV to tell the tiles apart
+                let tint = vertex.uv.fract().extend(0.0) * 0.5 + 0.5;
+                tint * if odd { 0.35 } else { 0.9 }
+            }
+            DebugMode::UvCoordinates => vertex.uv.fract().extend(0.0),
+            DebugMode::VertexColors => vertex.c,
+            DebugMode::TriangleIds => id_color(self.triangle),
+            DebugMode::MipLevel => {
+                let size = glam::vec2(texture.width as f32, texture.height as f32);
+                let footprint = (uv_dx * size).length().max((uv_dy * size).length());
+                let levels = size.max_element().log2().floor();
+                level_color(footprint.max(1.0).log2().min(levels))
+            }
+        };
+        to_argb8_v(255, (color * 255.0).clamp(Vec3::ZERO, Vec3::splat(255.0)))
+    }
+}
+
+// well spread color of a triangle index, never too dark to see
+pub fn id_color(id: usize) -> Vec3 {
+    let mut hash = (id as u32).wrapping_add(1).wrapping_mul(0x9e37_79b9);
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    from_argb8_v(hash) / 255.0 * 0.75 + 0.25
+}
+
+// blue, cyan, green, yellow and red ramp for `t` from 0.0 to 1.0
+pub fn heat_color(t: f32) -> Vec3 {
+    const RAMP: [Vec3; 5] = [
+        Vec3::new(0.0, 0.0, 1.0),
+        Vec3::new(0.0, 1.0, 1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(1.0, 1.0, 0.0),
+        Vec3::new(1.0, 0.0, 0.0),
+    ];
+    let position = t.clamp(0.0, 1.0) * (RAMP.len() - 1) as f32;
+    let index = (position as usize).min(RAMP.len() - 2);
+    RAMP[index].lerp(RAMP[index + 1], position - index as f32)
+}
+
+// mip level 0 is red, every next level gets a color further along the palette, fractions are blended
+pub fn level_color(level: f32) -> Vec3 {
+    const PALETTE: [Vec3; 8] = [
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(1.0, 0.5, 0.0),
+        Vec3::new(1.0, 1.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(0.0, 1.0, 1.0),
+        Vec3::new(0.0, 0.0, 1.0),
+        Vec3::new(0.5, 0.0, 1.0),
+        Vec3::new(1.0, 0.0, 1.0),
+    ];
+    let level = level.clamp(0.0, (PALETTE.len() - 1) as f32);
+    let index = (level as usize).min(PALETTE.len() - 2);
+    PALETTE[index].lerp(PALETTE[index + 1], level - index as f32)
+}
+
+impl Framebuffer {
+    pub fn enable_debug_view(&mut self, mode: DebugMode, camera: &Camera) {
+        self.debug_view = Some(DebugView::new(mode, camera, self.width, self.height));
+    }
+
+    pub fn disable_debug_view(&mut self) {
+        self.debug_view = None;
+    }
+
+    // Fills in the views that need the whole frame, call after `resolve`. The depth is scaled
+    // from the nearest to the farthest drawn pixel, where nothing was drawn stays black.
+    // The HDR buffer gets the debug colors too, so post effects work on what is shown.
+    pub fn resolve_debug_view(&mut self, camera: &Camera) {
+        let Some(debug_view) = self.debug_view.as_ref() else {
+            return;
+        };
+        match debug_view.mode {
+            DebugMode::Depth => {
+                let clear_depth = self.clear_depth();
+                let distances: Vec<Option<f32>> = self
+                    .depth
+                    .iter()
+                    .map(|z| (*z != clear_depth).then(|| camera.linearize_depth(*z)))
+                    .collect();
+                let (near, far) = distances
+                    .iter()
+                    .flatten()
+                    .fold((f32::MAX, f32::MIN), |(near, far), d| (near.min(*d), far.max(*d)));
+                let range = (far - near).max(f32::EPSILON);
+                for (color, distance) in self.color.iter_mut().zip(&distances) {
+                    let brightness = distance.map_or(0.0, |d| 1.0 - 0.9 * (d - near) / range);
+                    *color = to_argb8_v(255, Vec3::splat(brightness * 255.0));
+                }
+            }
+            DebugMode::Overdraw => {
+                for (color, count) in self.color.iter_mut().zip(&debug_view.overdraw) {
+                    let heat = if *count == 0 {
+                        Vec3::ZERO
+                    } else {
+                        heat_color((*count - 1) as f32 / (MAX_OVERDRAW - 1) as f32)
+                    };
+                    *color = to_argb8_v(255, heat * 255.0);
+                }
+            }
+            _ => {}
+        }
+        for (hdr, color) in self.hdr.iter_mut().zip(&self.color) {
+            *hdr = from_argb8_v(*color) / 255.0;
+        }
+    }
+}
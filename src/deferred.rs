@@ -0,0 +1,88 @@
+use glam::Vec3;
+
+use crate::framebuffer::Framebuffer;
+use crate::lighting::Surface;
+use crate::utils::from_argb8_v;
+
+// Surfaces of the nearest opaque lit fragments, shaded once per pixel after all draws.
+// Pixels drawn over by anything else are no longer `covered` and keep the color they got.
+#[derive(Debug, Clone)]
+pub struct GBuffer {
+    pub width: usize,
+    pub height: usize,
+    pub albedo: Vec<u32>,
+    // world space, unit length
+    pub normal: Vec<Vec3>,
+    pub surface: Vec<Surface>,
+    // HDR intensity of the draw
+    pub intensity: Vec<f32>,
+    pub depth: Vec<f32>,
+    pub covered: Vec<bool>,
+}
+
+impl GBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            albedo: vec![0; width * height],
+            normal: vec![Vec3::ZERO; width * height],
+            surface: vec![Surface::default(); width * height],
+            intensity: vec![1.0; width * height],
+            depth: vec![0.0; width * height],
+            covered: vec![false; width * height],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.covered.iter_mut().for_each(|c| *c = false);
+    }
+
+    pub fn write(&mut self, index: usize, albedo: u32, normal: Vec3, surface: Surface, intensity: f32, depth: f32) {
+        self.albedo[index] = albedo;
+        self.normal[index] = normal;
+        self.surface[index] = surface;
+        self.intensity[index] = intensity;
+        self.depth[index] = depth;
+        self.covered[index] = true;
+    }
+
+    // an opaque fragment that is not lit replaced the surface
+    pub fn discard(&mut self, index: usize) {
+        self.covered[index] = false;
+    }
+}
+
+impl Framebuffer {
+    pub fn enable_g_buffer(&mut self) {
+        self.g_buffer = Some(GBuffer::new(self.width, self.height));
+    }
+
+    pub fn disable_g_buffer(&mut self) {
+        self.g_buffer = None;
+    }
+
+    // The lighting pass, every covered pixel of the G-buffer is shaded with all the lights
+    // into `color` and `hdr`. Without lighting the albedo is written as it is.
+    // Runs after the samples are resolved, so multisampled edges of lit surfaces are not smoothed.
+    pub fn resolve_lighting(&mut self) {
+        let Some(g_buffer) = self.g_buffer.as_ref() else {
+            return;
+        };
+        for (index, _) in g_buffer.covered.iter().enumerate().filter(|(_, covered)| **covered) {
+            let albedo = g_buffer.albedo[index];
+            let argb = match self.lighting.as_ref() {
+                Some(lighting) => {
+                    let (x, y) = (index % self.width, index / self.width);
+                    let position = lighting.position(x, y, g_buffer.depth[index], self.width, self.height);
+                    lighting.shade_argb(albedo, g_buffer.normal[index], position, &g_buffer.surface[index])
+                }
+                None => albedo,
+            };
+            self.color[index] = argb;
+            if !self.hdr.is_empty() {
+                self.hdr[index] = from_argb8_v(argb) / 255.0 * g_buffer.intensity[index];
+            }
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use glam::{Vec2, Vec3};
+
+use crate::camera::Camera;
+use crate::framebuffer::Framebuffer;
+use crate::post_processing::{blur, fetch, pack, smoothstep, unpack, unpack_pixel, PostEffect};
+
+pub fn pixel_to_ndc(x: usize, y: usize, width: usize, height: usize) -> Vec2 {
+    glam::vec2(
+        (x as f32 + 0.5) / width as f32 * 2.0 - 1.0,
+        1.0 - (y as f32 + 0.5) / height as f32 * 2.0,
+    )
+}
+
+// nothing was drawn into the pixel
+pub fn is_background(depth: f32, clear_depth: f32) -> bool {
+    depth == clear_depth
+}
+
+// view-space positions of all pixels reconstructed from the depth buffer
+pub fn view_positions(target: &Framebuffer, camera: &Camera) -> Vec<Vec3> {
+    let inverse_projection = camera.projection().inverse();
+    target
+        .depth
+        .iter()
+        .enumerate()
+        .map(|(index, depth)| {
+            let ndc = pixel_to_ndc(index % target.width, index / target.width, target.width, target.height);
+            inverse_projection.project_point3(ndc.extend(*depth))
+        })
+        .collect()
+}
+
+// view-space normals from the differences of neighbouring positions,
+// the smaller difference is picked on each axis so edges do not smear the normals
+pub fn view_normals(positions: &[Vec3], depth: &[f32], clear_depth: f32, width: usize, height: usize) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::ZERO; positions.len()];
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            let index = x as usize + y as usize * width;
+            if is_background(depth[index], clear_depth) {
+                continue;
+            }
+            let p = positions[index];
+
+            let smaller = |a: Vec3, b: Vec3| if a.z.abs() < b.z.abs() { a } else { b };
+            let ddx = smaller(
+                fetch(positions, width, height, x + 1, y) - p,
+                p - fetch(positions, width, height, x - 1, y),
+            );
+            let ddy = smaller(
+                fetch(positions, width, height, x, y + 1) - p,
+                p - fetch(positions, width, height, x, y - 1),
+            );
+
+            let mut normal = ddx.cross(ddy).normalize_or_zero();
+            if normal.dot(p) > 0.0 {
+                normal = -normal;
+            }
+            normals[index] = normal;
+        }
+    }
+    normals
+}
+
+// cheap deterministic noise in 0..1
+fn hash(n: u32) -> f32 {
+    let mut n = n.wrapping_mul(0x27d4_eb2d);
+    n ^= n >> 15;
+    n = n.wrapping_mul(0x85eb_ca6b);
+    n ^= n >> 13;
+    (n & 0x00ff_ffff) as f32 / 0x0100_0000 as f32
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum FogMode {
+    Linear { start: f32, end: f32 },
+    Exponential { density: f32 },
+    ExponentialSquared { density: f32 },
+}
+
+// Distance fog, the distance is measured from the camera position
+pub struct Fog {
+    pub mode: FogMode,
+    pub color: Vec3,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            mode: FogMode::Exponential { density: 0.1 },
+            color: glam::vec3(0.55, 0.6, 0.65),
+        }
+    }
+}
+
+impl Fog {
+    // how much of the surface color stays visible at `distance`
+    pub fn visibility(&self, distance: f32) -> f32 {
+        match self.mode {
+            FogMode::Linear { start, end } => ((end - distance) / (end - start)).clamp(0.0, 1.0),
+            FogMode::Exponential { density } => (-density * distance).exp(),
+            FogMode::ExponentialSquared { density } => (-(density * distance).powi(2)).exp(),
+        }
+    }
+}
+
+impl PostEffect for Fog {
+    fn name(&self) -> &'static str {
+        "Fog"
+    }
+
+    fn apply(&mut self, target: &mut Framebuffer, camera: &Camera) {
+        let positions = view_positions(target, camera);
+        let clear_depth = target.clear_depth();
+        for (index, color) in target.color.iter_mut().enumerate() {
+            let visibility = if is_background(target.depth[index], clear_depth) {
+                0.0
+            } else {
+                self.visibility(positions[index].length())
+            };
+            *color = pack(self.color.lerp(unpack_pixel(*color), visibility));
+        }
+    }
+}
+
+// Screen-space ambient occlusion with a normal oriented hemisphere kernel
+pub struct Ssao {
+    pub radius: f32, // in view-space units
+    pub bias: f32,
+    pub intensity: f32,
+    pub blur_radius: usize,
+    kernel: Vec<Vec3>,
+}
+
+impl Ssao {
+    pub fn new(samples: usize) -> Self {
+        let kernel = (0..samples as u32)
+            .map(|i| {
+                let direction = glam::vec3(
+                    hash(i * 3) * 2.0 - 1.0,
+                    hash(i * 3 + 1) * 2.0 - 1.0,
+                    hash(i * 3 + 2),
+                )
+                .normalize_or_zero();
+                // more samples close to the origin
+                let scale = i as f32 / samples as f32;
+                direction * (0.1 + 0.9 * scale * scale)
+            })
+            .collect();
+
+        Self {
+            radius: 0.4,
+            bias: 0.02,
+            intensity: 1.5,
+            blur_radius: 2,
+            kernel,
+        }
+    }
+}
+
+impl Default for Ssao {
+    fn default() -> Self {
+        Self::new(16)
+    }
+}
+
+impl PostEffect for Ssao {
+    fn name(&self) -> &'static str {
+        "SSAO"
+    }
+
+    fn apply(&mut self, target: &mut Framebuffer, camera: &Camera) {
+        let (width, height) = (target.width, target.height);
+        let projection = camera.projection();
+        let positions = view_positions(target, camera);
+        let normals = view_normals(&positions, &target.depth, target.clear_depth(), width, height);
+
+        let mut occlusion = vec![Vec3::ONE; positions.len()];
+        for y in 0..height {
+            for x in 0..width {
+                let index = x + y * width;
+                if is_background(target.depth[index], target.clear_depth()) {
+                    continue;
+                }
+                let p = positions[index];
+                let n = normals[index];
+
+                // the kernel is rotated around the normal in a 4x4 pattern
+                let angle = hash((x % 4 + (y % 4) * 4) as u32 + 1000) * std::f32::consts::TAU;
+                let random = glam::vec3(angle.cos(), angle.sin(), 0.0);
+                let tangent = (random - n * random.dot(n)).normalize_or_zero();
+                let bitangent = n.cross(tangent);
+
+                let mut occluded = 0.0;
+                for k in &self.kernel {
+                    let sample = p + (tangent * k.x + bitangent * k.y + n * k.z) * self.radius;
+
+                    let ndc = projection.project_point3(sample);
+                    let sx = ((ndc.x + 1.0) * 0.5 * width as f32) as i32;
+                    let sy = ((1.0 - ndc.y) * 0.5 * height as f32) as i32;
+                    if sx < 0 || sy < 0 || sx >= width as i32 || sy >= height as i32 {
+                        continue;
+                    }
+
+                    let scene = positions[sx as usize + sy as usize * width];
+                    if scene.z >= sample.z + self.bias {
+                        occluded += smoothstep(0.0, 1.0, self.radius / (p.z - scene.z).abs());
+                    }
+                }
+
+                let ao = 1.0 - occluded / self.kernel.len() as f32 * self.intensity;
+                occlusion[index] = Vec3::splat(ao.clamp(0.0, 1.0));
+            }
+        }
+
+        let occlusion = blur(&occlusion, width, height, self.blur_radius);
+        for (color, ao) in target.color.iter_mut().zip(occlusion.iter()) {
+            *color = pack(unpack_pixel(*color) * ao.x);
+        }
+    }
+}
+
+// Gather based depth of field, the circle of confusion comes from
+// `Camera::focus_distance` and `Camera::aperture`
+pub struct DepthOfField {
+    pub max_radius: f32, // in pixels
+    pub samples: usize,
+}
+
+impl Default for DepthOfField {
+    fn default() -> Self {
+        Self {
+            max_radius: 8.0,
+            samples: 24,
+        }
+    }
+}
+
+impl DepthOfField {
+    // radius of the circle of confusion in pixels
+    pub fn circle_of_confusion(&self, camera: &Camera, distance: f32, height: usize) -> f32 {
+        let pixels_per_radian = height as f32 / (2.0 * (camera.fov * 0.5).tan());
+        let focus = camera.focus_distance.max(camera.frustum_near);
+        let angle = camera.aperture * (distance - focus).abs() / (distance * focus);
+        (angle * pixels_per_radian * 0.5).min(self.max_radius)
+    }
+}
+
+impl PostEffect for DepthOfField {
+    fn name(&self) -> &'static str {
+        "Depth of field"
+    }
+
+    fn apply(&mut self, target: &mut Framebuffer, camera: &Camera) {
+        let (width, height) = (target.width, target.height);
+        let image = unpack(&target.color);
+        let clear_depth = target.clear_depth();
+        let coc: Vec<f32> = target
+            .depth
+            .iter()
+            .map(|depth| {
+                let distance = if is_background(*depth, clear_depth) { camera.frustum_far } else { camera.linearize_depth(*depth) };
+                self.circle_of_confusion(camera, distance, height)
+            })
+            .collect();
+
+        // golden angle spiral
+        let offsets: Vec<Vec2> = (0..self.samples)
+            .map(|i| {
+                let r = ((i as f32 + 0.5) / self.samples as f32).sqrt();
+                let theta = i as f32 * 2.399_963;
+                glam::vec2(theta.cos(), theta.sin()) * r
+            })
+            .collect();
+
+        for y in 0..height {
+            for x in 0..width {
+                let index = x + y * width;
+                let radius = coc[index];
+                if radius < 0.5 {
+                    continue;
+                }
+
+                let mut sum = image[index];
+                let mut weight_sum = 1.0;
+                for offset in &offsets {
+                    let offset = *offset * radius;
+                    let sx = (x as f32 + offset.x) as i32;
+                    let sy = (y as f32 + offset.y) as i32;
+                    if sx < 0 || sy < 0 || sx >= width as i32 || sy >= height as i32 {
+                        continue;
+                    }
+                    let sample = sx as usize + sy as usize * width;
+
+                    // sharp samples in front must not bleed into the blurred background
+                    let in_front = if target.reverse_z {
+                        target.depth[sample] > target.depth[index]
+                    } else {
+                        target.depth[sample] < target.depth[index]
+                    };
+                    let weight = if in_front {
+                        (coc[sample] - offset.length() + 1.0).clamp(0.0, 1.0)
+                    } else {
+                        1.0
+                    };
+                    sum += image[sample] * weight;
+                    weight_sum += weight;
+                }
+
+                target.color[index] = pack(sum / weight_sum);
+            }
+        }
+    }
+}
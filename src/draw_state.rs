@@ -0,0 +1,234 @@
+use crate::lighting::Surface;
+use crate::transparency::BlendMode;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CompareFunc {
+    Never,
+    Less,
+    LessEqual,
+    Equal,
+    GreaterEqual,
+    Greater,
+    NotEqual,
+    Always,
+}
+
+impl CompareFunc {
+    // `value` is the incoming one, `reference` the stored one
+    pub fn test<T: PartialOrd>(self, value: T, reference: T) -> bool {
+        match self {
+            CompareFunc::Never => false,
+            CompareFunc::Less => value < reference,
+            CompareFunc::LessEqual => value <= reference,
+            CompareFunc::Equal => value == reference,
+            CompareFunc::GreaterEqual => value >= reference,
+            CompareFunc::Greater => value > reference,
+            CompareFunc::NotEqual => value != reference,
+            CompareFunc::Always => true,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DepthState {
+    pub compare: CompareFunc,
+    pub write: bool,
+    // constant offset in units of `DepthState::BIAS_UNIT`, added to the depth,
+    // negative values pull the geometry towards the camera (positive ones with reverse-Z)
+    pub bias: f32,
+    // offset multiplied with the steepest depth slope of the triangle per pixel
+    pub slope_scaled_bias: f32,
+}
+
+impl DepthState {
+    // smallest step of a 24 bit depth buffer
+    pub const BIAS_UNIT: f32 = 1.0 / 16_777_216.0;
+
+    // for a reverse-Z projection, where bigger values are closer to the camera
+    pub fn reversed() -> Self {
+        Self {
+            compare: CompareFunc::GreaterEqual,
+            ..Default::default()
+        }
+    }
+
+    pub fn offset(&self, max_slope: f32) -> f32 {
+        self.bias * Self::BIAS_UNIT + self.slope_scaled_bias * max_slope
+    }
+}
+
+impl Default for DepthState {
+    fn default() -> Self {
+        Self {
+            compare: CompareFunc::LessEqual,
+            write: true,
+            bias: 0.0,
+            slope_scaled_bias: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    Replace,
+    IncrementClamp,
+    DecrementClamp,
+    IncrementWrap,
+    DecrementWrap,
+    Invert,
+}
+
+impl StencilOp {
+    pub fn apply(self, value: u8, reference: u8) -> u8 {
+        match self {
+            StencilOp::Keep => value,
+            StencilOp::Zero => 0,
+            StencilOp::Replace => reference,
+            StencilOp::IncrementClamp => value.saturating_add(1),
+            StencilOp::DecrementClamp => value.saturating_sub(1),
+            StencilOp::IncrementWrap => value.wrapping_add(1),
+            StencilOp::DecrementWrap => value.wrapping_sub(1),
+            StencilOp::Invert => !value,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct StencilState {
+    pub enabled: bool,
+    // compares `reference & read_mask` with `stored & read_mask`
+    pub compare: CompareFunc,
+    pub reference: u8,
+    pub read_mask: u8,
+    pub write_mask: u8,
+    pub fail: StencilOp,       // stencil test failed
+    pub depth_fail: StencilOp, // stencil test passed, depth test failed
+    pub pass: StencilOp,       // both tests passed
+}
+
+impl StencilState {
+    pub fn test(&self, stored: u8) -> bool {
+        self.compare.test(self.reference & self.read_mask, stored & self.read_mask)
+    }
+
+    pub fn update(&self, stored: &mut u8, op: StencilOp) {
+        let value = op.apply(*stored, self.reference);
+        *stored = (*stored & !self.write_mask) | (value & self.write_mask);
+    }
+
+    // marks every drawn pixel with `reference`
+    pub fn write(reference: u8) -> Self {
+        Self {
+            enabled: true,
+            compare: CompareFunc::Always,
+            reference,
+            pass: StencilOp::Replace,
+            ..Default::default()
+        }
+    }
+
+    // only draws where the stencil buffer holds `reference`
+    pub fn equal(reference: u8) -> Self {
+        Self {
+            enabled: true,
+            compare: CompareFunc::Equal,
+            reference,
+            ..Default::default()
+        }
+    }
+
+    // only draws where the stencil buffer does not hold `reference`
+    pub fn not_equal(reference: u8) -> Self {
+        Self {
+            enabled: true,
+            compare: CompareFunc::NotEqual,
+            reference,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for StencilState {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            compare: CompareFunc::Always,
+            reference: 0,
+            read_mask: 0xff,
+            write_mask: 0xff,
+            fail: StencilOp::Keep,
+            depth_fail: StencilOp::Keep,
+            pass: StencilOp::Keep,
+        }
+    }
+}
+
+// How the triangles of a mesh are rasterized
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PolygonMode {
+    Fill,
+    Line,
+    Point,
+}
+
+// Fixed-function state of a single draw call
+#[derive(Debug, Copy, Clone)]
+pub struct DrawState {
+    pub blend: BlendMode,
+    pub opacity: f32, // multiplied with the texture alpha
+    pub intensity: f32, // scale of the color written into the HDR buffer
+    pub depth: DepthState,
+    pub stencil: StencilState,
+    // when disabled only depth and stencil are written
+    pub color_write: bool,
+    pub polygon_mode: PolygonMode,
+    pub line_width: f32, // in pixels
+    pub line_smooth: bool, // anti-aliased lines and round points
+    pub point_size: f32, // in pixels
+    // the texture is stretched over every point instead of using the vertex uv
+    pub point_sprite: bool,
+    // shaded with the lights of the target, overlays keep their flat color
+    pub lit: bool,
+    pub surface: Surface,
+}
+
+impl DrawState {
+    // runs the stencil and the depth test of a pixel or a sample, updating the stencil value
+    pub fn depth_stencil_test(&self, stencil: &mut u8, z: f32, stored_z: f32) -> bool {
+        if !self.stencil.enabled {
+            return self.depth.compare.test(z, stored_z);
+        }
+        if !self.stencil.test(*stencil) {
+            self.stencil.update(stencil, self.stencil.fail);
+            return false;
+        }
+        if !self.depth.compare.test(z, stored_z) {
+            self.stencil.update(stencil, self.stencil.depth_fail);
+            return false;
+        }
+        self.stencil.update(stencil, self.stencil.pass);
+        true
+    }
+}
+
+impl Default for DrawState {
+    fn default() -> Self {
+        Self {
+            blend: BlendMode::Opaque,
+            opacity: 1.0,
+            intensity: 1.0,
+            depth: DepthState::default(),
+            stencil: StencilState::default(),
+            color_write: true,
+            polygon_mode: PolygonMode::Fill,
+            line_width: 1.0,
+            line_smooth: false,
+            point_size: 1.0,
+            point_sprite: false,
+            lit: true,
+            surface: Surface::default(),
+        }
+    }
+}
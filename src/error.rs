@@ -0,0 +1,50 @@
+use std::fmt;
+
+// Problems an asset loader worked around, the asset is loaded without what they name
+pub type Warnings = Vec<String>;
+
+// Error of the asset loaders
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    Parse { line: usize, message: String },
+    // malformed binary data
+    Invalid(String),
+    Unsupported(String),
+    Gltf(gltf::Error),
+}
+
+impl LoadError {
+    pub fn parse(line: usize, message: impl Into<String>) -> Self {
+        LoadError::Parse {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "{}", error),
+            LoadError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            LoadError::Invalid(message) => write!(f, "invalid data: {}", message),
+            LoadError::Unsupported(message) => write!(f, "unsupported: {}", message),
+            LoadError::Gltf(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+    fn from(error: std::io::Error) -> Self {
+        LoadError::Io(error)
+    }
+}
+
+impl From<gltf::Error> for LoadError {
+    fn from(error: gltf::Error) -> Self {
+        LoadError::Gltf(error)
+    }
+}
@@ -0,0 +1,392 @@
+use glam::{Vec2, Vec3};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use gltf::json;
+use gltf::json::validation::Checked::Valid;
+use gltf::json::validation::USize64;
+
+use crate::geometry::{Mesh, Vertex};
+use crate::material::Material;
+
+// picks the format from the file extension, the inverse of `utils::load_mesh`
+pub fn export_mesh(mesh: &Mesh, materials: &[Material], path: &Path) -> io::Result<()> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    match extension.as_str() {
+        "gltf" => export_gltf(mesh, materials, path, false),
+        "glb" => export_gltf(mesh, materials, path, true),
+        "obj" => export_obj(mesh, materials, path),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("mesh format '{}' can not be exported", extension),
+        )),
+    }
+}
+
+// texture paths are written relative to the exported file when possible
+fn relative_path(file: &Path, directory: &Path) -> String {
+    let absolute = |p: &Path| p.canonicalize().unwrap_or_else(|_| p.to_path_buf());
+    let file = absolute(file);
+    let path = file.strip_prefix(absolute(directory)).map(Path::to_path_buf).unwrap_or(file);
+    path.to_string_lossy().replace('\\', "/")
+}
+
+fn has_normals(mesh: &Mesh) -> bool {
+    mesh.vertices().iter().any(|v| v.normal != Vec3::ZERO)
+}
+
+fn has_colors(mesh: &Mesh) -> bool {
+    mesh.vertices().iter().any(|v| v.c != Vec3::ONE)
+}
+
+// Wavefront OBJ, the materials go into a `.mtl` file next to it
+pub fn export_obj(mesh: &Mesh, materials: &[Material], path: &Path) -> io::Result<()> {
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut out = String::new();
+
+    if !materials.is_empty() {
+        let mtl_path = path.with_extension("mtl");
+        std::fs::write(&mtl_path, write_mtl(materials, directory))?;
+        let file_name = mtl_path.file_name().unwrap_or_default().to_string_lossy();
+        writeln!(out, "mtllib {}", file_name).unwrap();
+    }
+
+    // every vertex gets its own position, uv and normal so all three share the index
+    let colors = has_colors(mesh);
+    for v in mesh.vertices() {
+        if colors {
+            writeln!(out, "v {} {} {} {} {} {}", v.pos.x, v.pos.y, v.pos.z, v.c.x, v.c.y, v.c.z).unwrap();
+        } else {
+            writeln!(out, "v {} {} {}", v.pos.x, v.pos.y, v.pos.z).unwrap();
+        }
+    }
+    for v in mesh.vertices() {
+        writeln!(out, "vt {} {}", v.uv.x, 1.0 - v.uv.y).unwrap();
+    }
+    let normals = has_normals(mesh);
+    if normals {
+        for v in mesh.vertices() {
+            writeln!(out, "vn {} {} {}", v.normal.x, v.normal.y, v.normal.z).unwrap();
+        }
+    }
+
+    let corner = |i: u32| {
+        if normals {
+            format!("{0}/{0}/{0}", i + 1)
+        } else {
+            format!("{0}/{0}", i + 1)
+        }
+    };
+    for (name, triangles, material) in triangle_groups(mesh) {
+        writeln!(out, "g {}", if name.is_empty() { "default" } else { name }).unwrap();
+        if let Some(material) = material.and_then(|m| materials.get(m)) {
+            writeln!(out, "usemtl {}", material.name).unwrap();
+        }
+        for t in &mesh.triangles()[triangles] {
+            writeln!(out, "f {} {} {}", corner(t.x), corner(t.y), corner(t.z)).unwrap();
+        }
+    }
+    for line in mesh.lines() {
+        writeln!(out, "l {} {}", line.x + 1, line.y + 1).unwrap();
+    }
+    for point in mesh.points() {
+        writeln!(out, "p {}", point + 1).unwrap();
+    }
+
+    std::fs::write(path, out)
+}
+
+pub fn write_mtl(materials: &[Material], directory: &Path) -> String {
+    let mut out = String::new();
+    for m in materials {
+        writeln!(out, "newmtl {}", m.name).unwrap();
+        writeln!(out, "Kd {} {} {}", m.diffuse.x, m.diffuse.y, m.diffuse.z).unwrap();
+        writeln!(out, "Ks {} {} {}", m.specular.x, m.specular.y, m.specular.z).unwrap();
+        writeln!(out, "Ke {} {} {}", m.emissive.x, m.emissive.y, m.emissive.z).unwrap();
+        writeln!(out, "Ns {}", m.shininess).unwrap();
+        writeln!(out, "d {}", m.opacity).unwrap();
+        if let Some(map) = &m.diffuse_map {
+            writeln!(out, "map_Kd {}", relative_path(map, directory)).unwrap();
+        }
+        if let Some(map) = &m.bump_map {
+            writeln!(out, "map_Bump {}", relative_path(map, directory)).unwrap();
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// the groups of the mesh in triangle order, triangles outside of every group
+// (all of them when there are no groups) get unnamed groups without a material
+fn triangle_groups(mesh: &Mesh) -> Vec<(&str, std::ops::Range<usize>, Option<usize>)> {
+    let mut groups: Vec<_> = mesh
+        .groups()
+        .iter()
+        .map(|g| (g.name.as_str(), g.triangles.clone(), g.material))
+        .collect();
+    groups.sort_by_key(|(_, triangles, _)| triangles.start);
+
+    let mut gaps = Vec::new();
+    let mut covered = 0;
+    for (_, triangles, _) in &groups {
+        if triangles.start > covered {
+            gaps.push(("", covered..triangles.start, None));
+        }
+        covered = covered.max(triangles.end);
+    }
+    if mesh.triangles().len() > covered {
+        gaps.push(("", covered..mesh.triangles().len(), None));
+    }
+
+    groups.extend(gaps);
+    groups.sort_by_key(|(_, triangles, _)| triangles.start);
+    groups
+}
+
+// Appends accessors and their data to a single buffer
+struct GltfBuilder {
+    root: json::Root,
+    data: Vec<u8>,
+}
+
+impl GltfBuilder {
+    fn view(&mut self, bytes: &[u8], target: json::buffer::Target) -> json::Index<json::buffer::View> {
+        let offset = self.data.len();
+        self.data.extend_from_slice(bytes);
+        // accessors need 4 byte alignment
+        while !self.data.len().is_multiple_of(4) {
+            self.data.push(0);
+        }
+        self.root.push(json::buffer::View {
+            buffer: json::Index::new(0),
+            byte_length: USize64::from(bytes.len()),
+            byte_offset: Some(USize64::from(offset)),
+            byte_stride: None,
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            target: Some(Valid(target)),
+        })
+    }
+
+    fn accessor(
+        &mut self,
+        bytes: &[u8],
+        count: usize,
+        component_type: json::accessor::ComponentType,
+        type_: json::accessor::Type,
+        bounds: Option<(Vec3, Vec3)>,
+    ) -> json::Index<json::Accessor> {
+        let target = if component_type == json::accessor::ComponentType::U32 {
+            json::buffer::Target::ElementArrayBuffer
+        } else {
+            json::buffer::Target::ArrayBuffer
+        };
+        let view = self.view(bytes, target);
+        self.root.push(json::Accessor {
+            buffer_view: Some(view),
+            byte_offset: None,
+            count: USize64::from(count),
+            component_type: Valid(json::accessor::GenericComponentType(component_type)),
+            extensions: Default::default(),
+            extras: Default::default(),
+            type_: Valid(type_),
+            min: bounds.map(|(min, _)| json::Value::from(min.to_array().to_vec())),
+            max: bounds.map(|(_, max)| json::Value::from(max.to_array().to_vec())),
+            name: None,
+            normalized: false,
+            sparse: None,
+        })
+    }
+
+    fn vec3s(&mut self, values: &[Vec3], bounds: bool) -> json::Index<json::Accessor> {
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_array()).flat_map(f32::to_le_bytes).collect();
+        let bounds = bounds.then(|| {
+            values.iter().fold((Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)), |(min, max), v| (min.min(*v), max.max(*v)))
+        });
+        self.accessor(&bytes, values.len(), json::accessor::ComponentType::F32, json::accessor::Type::Vec3, bounds)
+    }
+
+    fn vec2s(&mut self, values: &[Vec2]) -> json::Index<json::Accessor> {
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_array()).flat_map(f32::to_le_bytes).collect();
+        self.accessor(&bytes, values.len(), json::accessor::ComponentType::F32, json::accessor::Type::Vec2, None)
+    }
+
+    fn indices(&mut self, values: &[u32]) -> json::Index<json::Accessor> {
+        let bytes: Vec<u8> = values.iter().flat_map(|i| i.to_le_bytes()).collect();
+        self.accessor(&bytes, values.len(), json::accessor::ComponentType::U32, json::accessor::Type::Scalar, None)
+    }
+
+    fn material(&mut self, material: &Material, directory: &Path) -> json::Index<json::Material> {
+        let base_color_texture = material.diffuse_map.as_ref().map(|map| {
+            let image = self.root.push(json::Image {
+                buffer_view: None,
+                mime_type: None,
+                name: None,
+                uri: Some(relative_path(map, directory)),
+                extensions: None,
+                extras: Default::default(),
+            });
+            let texture = self.root.push(json::Texture {
+                name: None,
+                sampler: None,
+                source: image,
+                extensions: None,
+                extras: Default::default(),
+            });
+            json::texture::Info {
+                index: texture,
+                tex_coord: 0,
+                extensions: None,
+                extras: Default::default(),
+            }
+        });
+
+        // the emissive factor is limited to 0..1, the rest goes into the strength
+        let strength = material.emissive.max_element().max(1.0);
+        let extensions = (strength > 1.0).then(|| {
+            self.use_extension("KHR_materials_emissive_strength");
+            json::extensions::material::Material {
+                emissive_strength: Some(json::extensions::material::EmissiveStrength {
+                    emissive_strength: json::extensions::material::EmissiveStrengthFactor(strength),
+                }),
+                ..Default::default()
+            }
+        });
+
+        let d = material.diffuse;
+        self.root.push(json::Material {
+            name: Some(material.name.clone()),
+            alpha_mode: Valid(if material.opacity < 1.0 {
+                json::material::AlphaMode::Blend
+            } else {
+                json::material::AlphaMode::Opaque
+            }),
+            pbr_metallic_roughness: json::material::PbrMetallicRoughness {
+                base_color_factor: json::material::PbrBaseColorFactor([d.x, d.y, d.z, material.opacity]),
+                base_color_texture,
+                ..Default::default()
+            },
+            emissive_factor: json::material::EmissiveFactor((material.emissive / strength).to_array()),
+            extensions,
+            ..Default::default()
+        })
+    }
+
+    fn use_extension(&mut self, name: &str) {
+        if !self.root.extensions_used.iter().any(|e| e == name) {
+            self.root.extensions_used.push(name.to_string());
+        }
+    }
+}
+
+// `.gltf` with the buffer in a `.bin` file next to it, or a single `.glb` when `binary`.
+// A glTF mesh needs at least one primitive, meshes without triangles, lines or points are an error.
+pub fn export_gltf(mesh: &Mesh, materials: &[Material], path: &Path, binary: bool) -> io::Result<()> {
+    if mesh.triangles().is_empty() && mesh.lines().is_empty() && mesh.points().is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "an empty mesh can not be exported to glTF"));
+    }
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut builder = GltfBuilder {
+        root: json::Root::default(),
+        data: Vec::new(),
+    };
+
+    let material_indices: Vec<_> = materials.iter().map(|m| builder.material(m, directory)).collect();
+    let (normals, colors) = (has_normals(mesh), has_colors(mesh));
+
+    let mut primitives = Vec::new();
+    let mut primitive = |builder: &mut GltfBuilder, indices: Vec<u32>, mode, material: Option<usize>| {
+        // every primitive only gets the vertices it uses, so loaders do not duplicate the whole mesh
+        let mut used = indices.clone();
+        used.sort_unstable();
+        used.dedup();
+        let vertices: Vec<Vertex> = used.iter().map(|i| mesh.vertices()[*i as usize]).collect();
+        let indices: Vec<u32> = indices.iter().map(|i| used.binary_search(i).unwrap_or(0) as u32).collect();
+
+        let mut attributes = BTreeMap::new();
+        let positions: Vec<Vec3> = vertices.iter().map(|v| v.pos).collect();
+        attributes.insert(Valid(json::mesh::Semantic::Positions), builder.vec3s(&positions, true));
+        if normals {
+            let normals: Vec<Vec3> = vertices.iter().map(|v| v.normal).collect();
+            attributes.insert(Valid(json::mesh::Semantic::Normals), builder.vec3s(&normals, false));
+        }
+        if colors {
+            let colors: Vec<Vec3> = vertices.iter().map(|v| v.c).collect();
+            attributes.insert(Valid(json::mesh::Semantic::Colors(0)), builder.vec3s(&colors, false));
+        }
+        let uvs: Vec<Vec2> = vertices.iter().map(|v| v.uv).collect();
+        attributes.insert(Valid(json::mesh::Semantic::TexCoords(0)), builder.vec2s(&uvs));
+
+        primitives.push(json::mesh::Primitive {
+            attributes,
+            extensions: Default::default(),
+            extras: Default::default(),
+            indices: Some(builder.indices(&indices)),
+            material: material.and_then(|m| material_indices.get(m).copied()),
+            mode: Valid(mode),
+            targets: None,
+        });
+    };
+    // empty groups would give primitives without vertices
+    for (_, triangles, material) in triangle_groups(mesh).into_iter().filter(|(_, triangles, _)| !triangles.is_empty()) {
+        let indices = mesh.triangles()[triangles].iter().flat_map(|t| t.to_array()).collect();
+        primitive(&mut builder, indices, json::mesh::Mode::Triangles, material);
+    }
+    if !mesh.lines().is_empty() {
+        let indices = mesh.lines().iter().flat_map(|l| l.to_array()).collect();
+        primitive(&mut builder, indices, json::mesh::Mode::Lines, None);
+    }
+    if !mesh.points().is_empty() {
+        primitive(&mut builder, mesh.points().clone(), json::mesh::Mode::Points, None);
+    }
+
+    let json_mesh = builder.root.push(json::Mesh {
+        extensions: Default::default(),
+        extras: Default::default(),
+        name: None,
+        primitives,
+        weights: None,
+    });
+    let node = builder.root.push(json::Node {
+        mesh: Some(json_mesh),
+        ..Default::default()
+    });
+    let scene = builder.root.push(json::Scene {
+        extensions: Default::default(),
+        extras: Default::default(),
+        name: None,
+        nodes: vec![node],
+    });
+    builder.root.scene = Some(scene);
+
+    let bin_path: PathBuf = path.with_extension("bin");
+    builder.root.push(json::Buffer {
+        byte_length: USize64::from(builder.data.len()),
+        extensions: Default::default(),
+        extras: Default::default(),
+        name: None,
+        uri: (!binary).then(|| bin_path.file_name().unwrap_or_default().to_string_lossy().into_owned()),
+    });
+
+    let to_io = |error: json::Error| io::Error::new(io::ErrorKind::InvalidData, error);
+    if binary {
+        let glb = gltf::binary::Glb {
+            header: gltf::binary::Header {
+                magic: *b"glTF",
+                version: 2,
+                length: 0, // computed by `to_writer`
+            },
+            json: Cow::Owned(json::serialize::to_vec(&builder.root).map_err(to_io)?),
+            bin: Some(Cow::Owned(builder.data)),
+        };
+        let file = std::fs::File::create(path)?;
+        glb.to_writer(file).map_err(|error| io::Error::other(error.to_string()))
+    } else {
+        std::fs::write(&bin_path, &builder.data)?;
+        std::fs::write(path, json::serialize::to_vec_pretty(&builder.root).map_err(to_io)?)
+    }
+}
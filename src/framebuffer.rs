@@ -0,0 +1,197 @@
+use glam::{Vec2, Vec3};
+
+use crate::antialiasing;
+use crate::debug_view::DebugView;
+use crate::deferred::GBuffer;
+use crate::hiz::HiZ;
+use crate::lighting::Lighting;
+use crate::transparency::ABuffer;
+
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub color: Vec<u32>,
+    pub depth: Vec<f32>,
+    pub stencil: Vec<u8>,
+    // only allocated when order-independent transparency is used
+    pub a_buffer: Option<ABuffer>,
+    // MSAA storage, `samples` values per pixel, empty when multisampling is off
+    pub samples: usize,
+    pub sample_color: Vec<u32>,
+    pub sample_depth: Vec<f32>,
+    pub sample_stencil: Vec<u8>,
+    // linear color without clamping, empty unless `enable_hdr` was called
+    pub hdr: Vec<Vec3>,
+    // depth is cleared to 0.0 and bigger values are closer, see `Camera::reverse_z`
+    pub reverse_z: bool,
+    // min/max depth per tile, none unless `enable_hiz` was called
+    pub hiz: Option<HiZ>,
+    // deferred shading, lit opaque fragments are stored and shaded in `resolve_lighting`
+    pub g_buffer: Option<GBuffer>,
+    // lights of the frame, lit draws are shaded with them, unlit when none
+    pub lighting: Option<Lighting>,
+    // fragments get debug colors instead of being shaded, none unless `enable_debug_view` was called
+    pub debug_view: Option<DebugView>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            color: vec![0; width * height],
+            depth: vec![1.0; width * height],
+            stencil: vec![0; width * height],
+            a_buffer: None,
+            samples: 1,
+            sample_color: Vec::new(),
+            sample_depth: Vec::new(),
+            sample_stencil: Vec::new(),
+            hdr: Vec::new(),
+            reverse_z: false,
+            hiz: None,
+            g_buffer: None,
+            lighting: None,
+            debug_view: None,
+        }
+    }
+
+    pub fn clear_depth(&self) -> f32 {
+        if self.reverse_z { 0.0 } else { 1.0 }
+    }
+
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        self.reverse_z = reverse_z;
+        if let Some(a_buffer) = self.a_buffer.as_mut() {
+            a_buffer.reverse_z = reverse_z;
+        }
+        self.reset_hiz();
+    }
+
+    pub fn size(&self) -> Vec2 {
+        glam::vec2(self.width as f32, self.height as f32)
+    }
+
+    pub fn enable_a_buffer(&mut self, budget_bytes: usize) {
+        let mut a_buffer = ABuffer::with_budget(self.width, self.height, budget_bytes);
+        a_buffer.reverse_z = self.reverse_z;
+        self.a_buffer = Some(a_buffer);
+    }
+
+    pub fn enable_hiz(&mut self, tile_size: usize) {
+        self.hiz = Some(HiZ::new(self.width, self.height, tile_size, self.clear_depth()));
+    }
+
+    pub fn disable_hiz(&mut self) {
+        self.hiz = None;
+    }
+
+    // the depth buffer changed behind the back of the rasterizer, every tile is gathered again
+    pub fn reset_hiz(&mut self) {
+        if let Some(hiz) = self.hiz.as_mut() {
+            hiz.invalidate();
+        }
+        self.update_hiz();
+    }
+
+    pub fn enable_hdr(&mut self) {
+        self.hdr = vec![Vec3::ZERO; self.width * self.height];
+    }
+
+    // 1 disables multisampling, otherwise 2, 4 or 8, other counts are rounded down to one of them
+    pub fn set_samples(&mut self, samples: usize) {
+        let samples = antialiasing::supported_samples(samples);
+        self.samples = samples;
+        if samples > 1 {
+            self.sample_color = vec![0; self.width * self.height * samples];
+            self.sample_depth = vec![self.clear_depth(); self.width * self.height * samples];
+            self.sample_stencil = vec![0; self.width * self.height * samples];
+        } else {
+            self.sample_color = Vec::new();
+            self.sample_depth = Vec::new();
+            self.sample_stencil = Vec::new();
+        }
+        self.reset_hiz();
+    }
+
+    pub fn clear(&mut self) {
+        let clear_depth = self.clear_depth();
+        self.color.iter_mut().for_each(|c| *c = 0);
+        self.depth.iter_mut().for_each(|z| *z = clear_depth);
+        self.sample_color.iter_mut().for_each(|c| *c = 0);
+        self.sample_depth.iter_mut().for_each(|z| *z = clear_depth);
+        self.clear_stencil(0);
+        self.hdr.iter_mut().for_each(|c| *c = Vec3::ZERO);
+        if let Some(a_buffer) = self.a_buffer.as_mut() {
+            a_buffer.clear();
+        }
+        if let Some(hiz) = self.hiz.as_mut() {
+            hiz.clear(clear_depth);
+        }
+        if let Some(g_buffer) = self.g_buffer.as_mut() {
+            g_buffer.clear();
+        }
+        if let Some(debug_view) = self.debug_view.as_mut() {
+            debug_view.clear();
+        }
+    }
+
+    pub fn clear_stencil(&mut self, value: u8) {
+        self.stencil.iter_mut().for_each(|s| *s = value);
+        self.sample_stencil.iter_mut().for_each(|s| *s = value);
+    }
+
+    // averages the samples into `color`, `depth` keeps the nearest sample
+    pub fn resolve_samples(&mut self) {
+        if self.samples <= 1 {
+            return;
+        }
+        antialiasing::resolve_samples(&self.sample_color, self.samples, &mut self.color);
+        let reverse_z = self.reverse_z;
+        for (index, z) in self.depth.iter_mut().enumerate() {
+            let samples = self.sample_depth[index * self.samples..(index + 1) * self.samples].iter();
+            *z = if reverse_z {
+                samples.fold(0.0, |nearest: f32, z| nearest.max(*z))
+            } else {
+                samples.fold(1.0, |nearest: f32, z| nearest.min(*z))
+            };
+        }
+        for (index, stencil) in self.stencil.iter_mut().enumerate() {
+            *stencil = self.sample_stencil[index * self.samples];
+        }
+    }
+
+    // composites the fragments collected in the A-buffer, call after all draws of a frame
+    pub fn resolve_transparency(&mut self) {
+        if let Some(a_buffer) = self.a_buffer.as_mut() {
+            a_buffer.resolve(&mut self.color, &self.depth);
+        }
+    }
+
+    pub fn resolve(&mut self) {
+        self.resolve_samples();
+        self.resolve_lighting();
+        self.resolve_transparency();
+    }
+
+    // box filters a supersampled framebuffer into a smaller one
+    pub fn downsample_into(&self, dst: &mut Framebuffer) {
+        let factor = self.width / dst.width;
+        antialiasing::downsample(&self.color, self.width, factor, &mut dst.color, dst.width);
+        for (index, z) in dst.depth.iter_mut().enumerate() {
+            let (x, y) = (index % dst.width, index / dst.width);
+            *z = self.depth[(y * factor) * self.width + x * factor];
+        }
+        if !self.hdr.is_empty() && !dst.hdr.is_empty() {
+            let weight = 1.0 / (factor * factor) as f32;
+            for (index, c) in dst.hdr.iter_mut().enumerate() {
+                let (x, y) = (index % dst.width, index / dst.width);
+                *c = Vec3::ZERO;
+                for sy in 0..factor {
+                    let row = (y * factor + sy) * self.width + x * factor;
+                    *c += self.hdr[row..row + factor].iter().sum::<Vec3>() * weight;
+                }
+            }
+        }
+    }
+}
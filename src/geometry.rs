@@ -1,19 +1,59 @@
-use glam::{Vec2, Vec3, Mat4, Vec4Swizzles, UVec3};
-use std::ops::{Add, Mul, Sub};
+use glam::{Affine2, Vec2, Vec3, Vec4, Mat4, Vec4Swizzles, UVec2, UVec3, UVec4};
+use std::ops::{Add, Mul, Range, Sub};
+
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::material::Material;
+use crate::normals::{apply_corner_normals, corner_normals, NormalMode};
+use crate::morph::{morph_vertices, MorphTarget};
+use crate::processing;
+use crate::skinning::{skin_vertices, JointWeights, SkinningMethod};
 
 
 
 #[derive(Debug, Clone)]
 pub struct Mesh {
     triangles: Vec<UVec3>,
+    lines: Vec<UVec2>,
+    points: Vec<u32>,
     vertices: Vec<Vertex>,
+    groups: Vec<MeshGroup>,
+    // one per vertex for skinned meshes, empty otherwise
+    joint_weights: Vec<JointWeights>,
+    // blend shapes with their deltas for every vertex, and the weights they are used with by default
+    morph_targets: Vec<MorphTarget>,
+    morph_weights: Vec<f32>,
+    // bounds of the vertices in model space, kept up to date when vertices are added
+    aabb: Aabb,
+    bounding_sphere: BoundingSphere,
+}
+
+// Named range of triangles sharing a material
+#[derive(Debug, Clone)]
+pub struct MeshGroup {
+    pub name: String,
+    pub triangles: Range<usize>,
+    pub material: Option<usize>, // index into the materials loaded with the mesh
+}
+
+impl Default for Mesh {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Mesh {
     pub fn new() -> Self {
         Self {
             triangles: Vec::new(),
+            lines: Vec::new(),
+            points: Vec::new(),
             vertices: Vec::new(),
+            groups: Vec::new(),
+            joint_weights: Vec::new(),
+            morph_targets: Vec::new(),
+            morph_weights: Vec::new(),
+            aabb: Aabb::EMPTY,
+            bounding_sphere: BoundingSphere::default(),
         }
     }
 
@@ -21,10 +61,57 @@ impl Mesh {
         &self.triangles
     }
 
+    pub fn lines(&self) -> &Vec<UVec2> {
+        &self.lines
+    }
+
+    pub fn points(&self) -> &Vec<u32> {
+        &self.points
+    }
+
+    pub fn groups(&self) -> &Vec<MeshGroup> {
+        &self.groups
+    }
+
     pub fn vertices(&self) -> &Vec<Vertex> {
         &self.vertices
     }
 
+    pub fn joint_weights(&self) -> &Vec<JointWeights> {
+        &self.joint_weights
+    }
+
+    pub fn is_skinned(&self) -> bool {
+        !self.joint_weights.is_empty()
+    }
+
+    pub fn morph_targets(&self) -> &Vec<MorphTarget> {
+        &self.morph_targets
+    }
+
+    pub fn morph_weights(&self) -> &Vec<f32> {
+        &self.morph_weights
+    }
+
+    // missing weights are zero, extra ones are ignored
+    pub fn set_morph_weights(&mut self, weights: &[f32]) {
+        self.morph_weights = (0..self.morph_targets.len()).map(|i| weights.get(i).copied().unwrap_or(0.0)).collect();
+    }
+
+    pub fn aabb(&self) -> &Aabb {
+        &self.aabb
+    }
+
+    pub fn bounding_sphere(&self) -> &BoundingSphere {
+        &self.bounding_sphere
+    }
+
+    fn update_bounds(&mut self) {
+        let positions = self.vertices.iter().map(|v| v.pos);
+        self.aabb = Aabb::from_points(positions.clone());
+        self.bounding_sphere = BoundingSphere::from_points(positions);
+    }
+
     pub fn get_vertices_from_triangle(&self, triangle: UVec3) -> [&Vertex; 3] {
         [
             &self.vertices[triangle.x as usize],
@@ -45,8 +132,187 @@ impl Mesh {
         let triangles: Vec<UVec3> = triangles.iter().map(|tri| *tri + offset).collect();
         self.triangles.extend_from_slice(&triangles);
         self.vertices.extend_from_slice(vertices);
+        self.pad_vertex_attributes();
+        self.update_bounds();
+    }
+
+    // Copy of the mesh with its vertices moved by the vertex stage: blended with the morph targets
+    // (the mesh weights when `morph_weights` is none), then skinned with `joint_matrices`,
+    // which are indexed like the joints of the skin.
+    pub fn deform(&self, morph_weights: Option<&[f32]>, joint_matrices: Option<&[Mat4]>, method: SkinningMethod) -> Mesh {
+        let mut mesh = self.clone();
+        if !self.morph_targets.is_empty() {
+            let weights = morph_weights.unwrap_or(&self.morph_weights);
+            mesh.vertices = morph_vertices(&mesh.vertices, &self.morph_targets, weights);
+        }
+        if let (Some(matrices), true) = (joint_matrices, self.is_skinned()) {
+            mesh.vertices = skin_vertices(&mesh.vertices, &self.joint_weights, matrices, method);
+        }
+        mesh.update_bounds();
+        mesh
+    }
+
+    // vertices added without weights or deltas are not moved by the skin and the morph targets
+    fn pad_vertex_attributes(&mut self) {
+        if self.is_skinned() {
+            self.joint_weights.resize(self.vertices.len(), JointWeights::default());
+        }
+        for target in &mut self.morph_targets {
+            target.pad(self.vertices.len());
+        }
+    }
+
+    // the attributes kept beside the vertices, for vertices appended as copies of `sources`
+    fn copy_vertex_attributes(&mut self, sources: &[u32]) {
+        let sources: Vec<usize> = sources.iter().map(|i| *i as usize).collect();
+        if self.is_skinned() {
+            let copies: Vec<JointWeights> = sources.iter().map(|i| self.joint_weights[*i]).collect();
+            self.joint_weights.extend(copies);
+        }
+        let count = self.vertices.len() - sources.len();
+        for target in &mut self.morph_targets {
+            let copies = target.select(&sources);
+            target.append(count, &copies);
+        }
+    }
+
+    fn same_vertex_attributes(&self, a: usize, b: usize) -> bool {
+        (!self.is_skinned() || self.joint_weights[a] == self.joint_weights[b])
+            && self.morph_targets.iter().all(|target| target.same_deltas(a, b))
+    }
+
+    // indices are into the vertices already in the mesh
+    pub fn add_lines(&mut self, lines: &[UVec2]) {
+        self.lines.extend_from_slice(lines);
+    }
+
+    pub fn add_points(&mut self, points: &[u32]) {
+        self.points.extend_from_slice(points);
+    }
+
+    pub fn add_group(&mut self, group: MeshGroup) {
+        self.groups.push(group);
+    }
+
+    // recomputes the normals of every triangle, vertices on hard edges are split
+    pub fn compute_normals(&mut self, mode: NormalMode) {
+        self.generate_normals(0..self.triangles.len(), mode);
+    }
+
+    pub fn generate_normals(&mut self, triangles: Range<usize>, mode: NormalMode) {
+        let normals = corner_normals(&self.triangles[triangles.clone()], &self.vertices, mode);
+        let sources = apply_corner_normals(&mut self.triangles[triangles], &mut self.vertices, &normals);
+        self.copy_vertex_attributes(&sources);
+    }
+
+    // Merges vertices closer than `tolerance` with the same attributes, returns how many were removed.
+    // Meshes with a normal per face need smooth normals before anything can be merged.
+    pub fn weld(&mut self, tolerance: f32) -> usize {
+        let mut map = processing::weld_map(&self.vertices, tolerance, true);
+        // vertices moved by different joints or morph targets stay apart
+        for (i, target) in map.iter_mut().enumerate() {
+            if !self.same_vertex_attributes(*target as usize, i) {
+                *target = i as u32;
+            }
+        }
+        let count = self.vertices.len();
+        self.remap_vertices(|i| map[i as usize]);
+        self.compact_vertices();
+        count - self.vertices.len()
+    }
+
+    // returns how many triangles were removed
+    pub fn remove_degenerate_triangles(&mut self) -> usize {
+        let count = self.triangles.len();
+        let degenerate: Vec<bool> = self.triangles.iter().map(|t| processing::is_degenerate(*t, &self.vertices)).collect();
+        self.retain_triangles(|t| !degenerate[t]);
+        count - self.triangles.len()
+    }
+
+    // reorders the triangles of each group for the vertex cache and the vertices in the order they are used
+    pub fn optimize_vertex_cache(&mut self) {
+        for range in self.group_ranges() {
+            processing::optimize_vertex_cache(&mut self.triangles[range]);
+        }
+        self.compact_vertices();
+    }
+
+    // Collapses edges until at most `target_triangles` are left, borders are kept and texture
+    // seams only shortened along themselves. The result can stay above the target, unwelded
+    // meshes hardly simplify at all, `weld` them first.
+    pub fn simplify(&mut self, target_triangles: usize) {
+        let alive = processing::simplify(&mut self.triangles, &self.vertices, target_triangles);
+        self.retain_triangles(|t| alive[t]);
+        self.compact_vertices();
     }
 
+    // the triangle ranges of the groups and of the triangles between them
+    fn group_ranges(&self) -> Vec<Range<usize>> {
+        let mut bounds: Vec<usize> = self.groups.iter().flat_map(|g| [g.triangles.start, g.triangles.end]).collect();
+        bounds.extend([0, self.triangles.len()]);
+        bounds.sort_unstable();
+        bounds.dedup();
+        bounds.windows(2).map(|w| w[0]..w[1]).collect()
+    }
+
+    fn retain_triangles(&mut self, keep: impl Fn(usize) -> bool) {
+        // number of triangles kept before each index, to move the group ranges
+        let mut kept_before = Vec::with_capacity(self.triangles.len() + 1);
+        kept_before.push(0);
+        for t in 0..self.triangles.len() {
+            kept_before.push(kept_before[t] + keep(t) as usize);
+        }
+        for group in &mut self.groups {
+            group.triangles = kept_before[group.triangles.start]..kept_before[group.triangles.end];
+        }
+        let mut t = 0;
+        self.triangles.retain(|_| {
+            t += 1;
+            keep(t - 1)
+        });
+    }
+
+    fn remap_vertices(&mut self, map: impl Fn(u32) -> u32) {
+        for triangle in &mut self.triangles {
+            *triangle = UVec3::from_array(triangle.to_array().map(&map));
+        }
+        for line in &mut self.lines {
+            *line = UVec2::from_array(line.to_array().map(&map));
+        }
+        for point in &mut self.points {
+            *point = map(*point);
+        }
+    }
+
+    // drops the unused vertices and sorts the rest in the order they are first used
+    fn compact_vertices(&mut self) {
+        let used = self
+            .triangles
+            .iter()
+            .flat_map(|t| t.to_array())
+            .chain(self.lines.iter().flat_map(|l| l.to_array()))
+            .chain(self.points.iter().copied());
+        let mut map = vec![u32::MAX; self.vertices.len()];
+        let mut order = Vec::with_capacity(self.vertices.len());
+        for i in used {
+            if map[i as usize] == u32::MAX {
+                map[i as usize] = order.len() as u32;
+                order.push(i as usize);
+            }
+        }
+        self.vertices = order.iter().map(|i| self.vertices[*i]).collect();
+        if self.is_skinned() {
+            self.joint_weights = order.iter().map(|i| self.joint_weights[*i]).collect();
+        }
+        for target in &mut self.morph_targets {
+            *target = target.select(&order);
+        }
+        self.remap_vertices(|i| map[i as usize]);
+        self.update_bounds();
+    }
+
+    // Appends a section of vertices made from the buffers, missing normals are generated with the
+    // default smoothing. The indices of `triangles` are into all the vertices of the mesh.
     pub fn add_section_from_buffers(
         &mut self,
         triangles: &[UVec3],
@@ -55,30 +321,70 @@ impl Mesh {
         colors: &[Vec3],
         uvs: &[Vec2],
     ) {
+        self.add_section(triangles, positions, normals, colors, uvs, &[], &[], NormalMode::default());
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn add_section(
+        &mut self,
+        triangles: &[UVec3],
+        positions: &[Vec3],
+        normals: &[Vec3],
+        colors: &[Vec3],
+        uvs: &[Vec2],
+        joint_weights: &[JointWeights],
+        morph_targets: &[MorphTarget],
+        missing_normals: NormalMode,
+    ) {
+        let offset = self.vertices.len() as u32;
+        let first_triangle = self.triangles.len();
         self.triangles.extend_from_slice(triangles);
 
         let has_uvs = !uvs.is_empty();
         let has_colors = !colors.is_empty();
+        // lines and points usually come without normals
+        let has_normals = !normals.is_empty();
 
         for i in 0..positions.len() {
             let vertex = Vertex::new(
                 positions[i],
-                normals[i],
+                if has_normals { normals[i] } else { Vec3::ZERO },
                 if has_colors { colors[i] } else { Vec3::ONE },
                 if has_uvs { uvs[i] } else { Vec2::ZERO },
             );
             self.vertices.push(vertex)
         }
+        if !joint_weights.is_empty() {
+            self.joint_weights.resize(offset as usize, JointWeights::default());
+            self.joint_weights.extend(joint_weights.iter().take(positions.len()));
+        }
+        if self.morph_targets.len() < morph_targets.len() {
+            self.morph_targets.resize(morph_targets.len(), MorphTarget::default());
+            self.morph_weights.resize(morph_targets.len(), 0.0);
+        }
+        for (target, section) in self.morph_targets.iter_mut().zip(morph_targets) {
+            target.append(offset as usize, section);
+        }
+        self.pad_vertex_attributes();
+        self.update_bounds();
+
+        if !has_normals && !triangles.is_empty() {
+            self.generate_normals(first_triangle..self.triangles.len(), missing_normals);
+        }
     }
 
-    pub fn load_from_gltf(mesh: &gltf::Mesh, buffers: &[gltf::buffer::Data]) -> Mesh {
-        let mut positions: Vec<Vec3> = Vec::new();
-        let mut tex_coords: Vec<Vec2> = Vec::new();
-        let mut normals: Vec<Vec3> = Vec::new();
-        let mut indices = vec![];
-        // TODO: handle errors
+    // `materials` are the ones of the document, in the same order
+    pub fn load_from_gltf(mesh: &gltf::Mesh, buffers: &[gltf::buffer::Data], materials: &[Material]) -> Mesh {
         let mut result = Mesh::new();
         for primitive in mesh.primitives() {
+            let material_index = primitive.material().index();
+            let material = material_index.and_then(|i| materials.get(i));
+
+            let mut positions: Vec<Vec3> = Vec::new();
+            let mut tex_coords: Vec<Vec2> = Vec::new();
+            let mut normals: Vec<Vec3> = Vec::new();
+            let mut indices = vec![];
+
             let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
             if let Some(indices_reader) = reader.read_indices() {
                 indices_reader.into_u32().for_each(|i| indices.push(i));
@@ -89,27 +395,190 @@ impl Mesh {
             if let Some(normals_reader) = reader.read_normals() {
                 normals_reader.for_each(|n| normals.push(Vec3::new(n[0], n[1], n[2])));
             }
-            if let Some(tex_coord_reader) = reader.read_tex_coords(0) {
+            // the set used by the diffuse map, e.g. TEXCOORD_1 for lightmapped assets
+            let uv_set = material.map_or(0, |m| m.uv_set);
+            if let Some(tex_coord_reader) = reader.read_tex_coords(uv_set).or_else(|| reader.read_tex_coords(0)) {
+                let transform = material.map_or(Affine2::IDENTITY, |m| m.uv_transform);
                 tex_coord_reader
                     .into_f32()
-                    .for_each(|tc| tex_coords.push(Vec2::new(tc[0], tc[1])));
+                    .for_each(|tc| tex_coords.push(transform.transform_point2(Vec2::new(tc[0], tc[1]))));
             }
 
-            let colors: Vec<Vec3> = positions.iter().map(|_| Vec3::ONE).collect();
+            let joint_weights: Vec<JointWeights> = match (reader.read_joints(0), reader.read_weights(0)) {
+                (Some(joints), Some(weights)) => joints
+                    .into_u16()
+                    .zip(weights.into_f32())
+                    .map(|(j, w)| JointWeights {
+                        joints: UVec4::from_array(j.map(u32::from)),
+                        weights: Vec4::from_array(w),
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            };
+
+            // deltas that do not cover every vertex are dropped
+            let deltas = |deltas: Option<gltf::mesh::util::ReadPositionDisplacements>| -> Vec<Vec3> {
+                let deltas: Vec<Vec3> = deltas.map_or_else(Vec::new, |d| d.map(Vec3::from).collect());
+                if deltas.len() == positions.len() { deltas } else { Vec::new() }
+            };
+            let morph_targets: Vec<MorphTarget> = reader
+                .read_morph_targets()
+                .map(|(positions, normals, tangents)| MorphTarget {
+                    positions: deltas(positions),
+                    normals: deltas(normals),
+                    tangents: deltas(tangents),
+                })
+                .collect();
+
+            let colors: Vec<Vec3> = match reader.read_colors(0) {
+                Some(colors_reader) => colors_reader.into_rgb_f32().map(Vec3::from).collect(),
+                None => positions.iter().map(|_| Vec3::ONE).collect(),
+            };
             println!("Num indices: {:?}", indices.len());
             println!("tex_coords: {:?}", tex_coords.len());
             println!("positions: {:?}", positions.len());
 
-            let triangles: Vec<UVec3> = indices
-                .chunks_exact(3)
-                .map(|tri| UVec3::new(tri[0], tri[1], tri[2]))
-                .collect();
-            result.add_section_from_buffers(&triangles, &positions, &normals, &colors, &tex_coords)
+            let offset = result.vertices.len() as u32;
+            let mode = primitive.mode();
+
+            // non-indexed primitives use the vertices in order
+            if indices.is_empty() {
+                indices = (0..positions.len() as u32).collect();
+            }
+
+            let mut triangles: Vec<UVec3> = Vec::new();
+            match mode {
+                gltf::mesh::Mode::Triangles => triangles = Topology::TriangleList.triangulate(&indices),
+                gltf::mesh::Mode::TriangleStrip => triangles = Topology::TriangleStrip.triangulate(&indices),
+                gltf::mesh::Mode::TriangleFan => triangles = Topology::TriangleFan.triangulate(&indices),
+                gltf::mesh::Mode::Lines => {
+                    result.lines.extend(indices.chunks_exact(2).map(|l| UVec2::new(l[0], l[1]) + offset));
+                }
+                gltf::mesh::Mode::LineStrip => {
+                    result.lines.extend(indices.windows(2).map(|l| UVec2::new(l[0], l[1]) + offset));
+                }
+                gltf::mesh::Mode::LineLoop => {
+                    result.lines.extend(indices.windows(2).map(|l| UVec2::new(l[0], l[1]) + offset));
+                    if indices.len() > 2 {
+                        result.lines.push(UVec2::new(indices[indices.len() - 1], indices[0]) + offset);
+                    }
+                }
+                gltf::mesh::Mode::Points => {
+                    result.points.extend(indices.iter().map(|i| i + offset));
+                }
+            }
+            // the indices of a primitive are into its own vertices
+            triangles.iter_mut().for_each(|tri| *tri += offset);
+
+            let first_triangle = result.triangles.len();
+            // the specification asks for flat normals when they are missing
+            result.add_section(&triangles, &positions, &normals, &colors, &tex_coords, &joint_weights, &morph_targets, NormalMode::Flat);
+            if !triangles.is_empty() {
+                result.add_group(MeshGroup {
+                    name: mesh.name().unwrap_or_default().to_string(),
+                    triangles: first_triangle..result.triangles.len(),
+                    material: material_index,
+                });
+            }
+        }
+        if let Some(weights) = mesh.weights() {
+            result.set_morph_weights(weights);
         }
         result
     }
 }
 
+// How an index buffer is assembled into triangles
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Topology {
+    TriangleList,
+    TriangleStrip,
+    TriangleFan,
+}
+
+impl Topology {
+    // converts the indices into a triangle list with the winding of the first triangle,
+    // degenerate triangles used to stitch strips together are dropped
+    pub fn triangulate(self, indices: &[u32]) -> Vec<UVec3> {
+        let triangles: Vec<UVec3> = match self {
+            Topology::TriangleList => indices
+                .chunks_exact(3)
+                .map(|tri| UVec3::new(tri[0], tri[1], tri[2]))
+                .collect(),
+            // every second triangle of a strip is wound the other way
+            Topology::TriangleStrip => indices
+                .windows(3)
+                .enumerate()
+                .map(|(i, tri)| {
+                    if i % 2 == 0 {
+                        UVec3::new(tri[0], tri[1], tri[2])
+                    } else {
+                        UVec3::new(tri[1], tri[0], tri[2])
+                    }
+                })
+                .collect(),
+            Topology::TriangleFan => indices
+                .windows(2)
+                .skip(1)
+                .map(|edge| UVec3::new(indices[0], edge[0], edge[1]))
+                .collect(),
+        };
+
+        triangles
+            .into_iter()
+            .filter(|tri| tri.x != tri.y && tri.y != tri.z && tri.z != tri.x)
+            .collect()
+    }
+}
+
+// Ear clipping on the plane of the polygon, concave polygons are supported
+// as long as they do not intersect themselves. Keeps the winding of the polygon.
+pub fn triangulate_polygon(positions: &[Vec3]) -> Vec<[usize; 3]> {
+    let count = positions.len();
+    if count == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    // Newell's method, robust for non-planar polygons
+    let mut normal = Vec3::ZERO;
+    for i in 0..count {
+        let (a, b) = (positions[i], positions[(i + 1) % count]);
+        normal += glam::vec3((a.y - b.y) * (a.z + b.z), (a.z - b.z) * (a.x + b.x), (a.x - b.x) * (a.y + b.y));
+    }
+    let normal = normal.normalize_or_zero();
+    if normal == Vec3::ZERO {
+        return (1..count - 1).map(|i| [0, i, i + 1]).collect();
+    }
+
+    let mut remaining: Vec<usize> = (0..count).collect();
+    let mut triangles = Vec::with_capacity(count - 2);
+    let is_convex = |a: Vec3, b: Vec3, c: Vec3| (b - a).cross(c - b).dot(normal) > 0.0;
+    let inside = |p: Vec3, a: Vec3, b: Vec3, c: Vec3| {
+        (b - a).cross(p - a).dot(normal) >= 0.0
+            && (c - b).cross(p - b).dot(normal) >= 0.0
+            && (a - c).cross(p - c).dot(normal) >= 0.0
+    };
+
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i| {
+            let (ia, ib, ic) = (remaining[(i + n - 1) % n], remaining[i], remaining[(i + 1) % n]);
+            let (a, b, c) = (positions[ia], positions[ib], positions[ic]);
+            is_convex(a, b, c)
+                && remaining
+                    .iter()
+                    .filter(|&&j| j != ia && j != ib && j != ic)
+                    .all(|&j| !inside(positions[j], a, b, c))
+        });
+        // degenerate input, clip any vertex to make progress
+        let i = ear.unwrap_or(0);
+        triangles.push([remaining[(i + n - 1) % n], remaining[i], remaining[(i + 1) % n]]);
+        remaining.remove(i);
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    triangles
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Triangle {
     pub v0: Vertex,
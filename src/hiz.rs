@@ -0,0 +1,317 @@
+use glam::{Mat4, Vec2, Vec4Swizzles};
+
+use crate::bounds::Aabb;
+use crate::draw_state::{CompareFunc, DrawState};
+use crate::framebuffer::Framebuffer;
+use crate::raster::near_plane_distance;
+use crate::utils::map_to_range;
+
+pub const DEFAULT_TILE_SIZE: usize = 8;
+// triangles drawn between updates of the bounds within a draw
+pub const UPDATE_INTERVAL: usize = 1024;
+
+// Hierarchical depth, bounds of the depth of every tile of the depth buffer.
+// Writes only widen the bounds of their tiles, which are gathered again from the depth
+// buffer by `update` before the next draw. The bounds are never narrower than the depth.
+#[derive(Debug, Clone)]
+pub struct HiZ {
+    // a power of two, tiles are found with shifts
+    pub tile_size: usize,
+    tile_shift: u32,
+    pub columns: usize,
+    pub rows: usize,
+    min: Vec<f32>,
+    max: Vec<f32>,
+    dirty: Vec<bool>,
+    // the tiles marked in `dirty`
+    dirty_tiles: Vec<usize>,
+}
+
+impl HiZ {
+    pub fn new(width: usize, height: usize, tile_size: usize, clear_depth: f32) -> Self {
+        let tile_size = tile_size.next_power_of_two();
+        let columns = width.div_ceil(tile_size);
+        let rows = height.div_ceil(tile_size);
+        Self {
+            tile_size,
+            tile_shift: tile_size.trailing_zeros(),
+            columns,
+            rows,
+            min: vec![clear_depth; columns * rows],
+            max: vec![clear_depth; columns * rows],
+            dirty: vec![false; columns * rows],
+            dirty_tiles: Vec::new(),
+        }
+    }
+
+    pub fn clear(&mut self, clear_depth: f32) {
+        self.min.iter_mut().for_each(|z| *z = clear_depth);
+        self.max.iter_mut().for_each(|z| *z = clear_depth);
+        self.dirty.iter_mut().for_each(|d| *d = false);
+        self.dirty_tiles.clear();
+    }
+
+    pub fn tile_of(&self, x: usize, y: usize) -> usize {
+        (y >> self.tile_shift) * self.columns + (x >> self.tile_shift)
+    }
+
+    // inclusive range of tiles covered by the pixels `min` to `max`, none when it is off screen
+    pub fn tile_range(&self, min: Vec2, max: Vec2) -> Option<(usize, usize, usize, usize)> {
+        let size = self.tile_size as f32;
+        if max.x < 0.0 || max.y < 0.0 || min.x >= (self.columns * self.tile_size) as f32 || min.y >= (self.rows * self.tile_size) as f32 {
+            return None;
+        }
+        let column = |x: f32| ((x.max(0.0) / size) as usize).min(self.columns - 1);
+        let row = |y: f32| ((y.max(0.0) / size) as usize).min(self.rows - 1);
+        Some((column(min.x), row(min.y), column(max.x), row(max.y)))
+    }
+
+    pub fn bounds(&self, tile: usize) -> (f32, f32) {
+        (self.min[tile], self.max[tile])
+    }
+
+    // depth between `near` and `far` may have been written to the tile of pixel `x`, `y`
+    pub fn widen(&mut self, x: usize, y: usize, (near, far): (f32, f32)) {
+        let tile = self.tile_of(x, y);
+        self.widen_tile(tile, (near, far));
+    }
+
+    pub fn widen_range(&mut self, (x0, y0, x1, y1): (usize, usize, usize, usize), depth: (f32, f32)) {
+        for row in y0..=y1 {
+            for column in x0..=x1 {
+                self.widen_tile(row * self.columns + column, depth);
+            }
+        }
+    }
+
+    fn widen_tile(&mut self, tile: usize, (near, far): (f32, f32)) {
+        self.min[tile] = self.min[tile].min(near);
+        self.max[tile] = self.max[tile].max(far);
+        if !self.dirty[tile] {
+            self.dirty[tile] = true;
+            self.dirty_tiles.push(tile);
+        }
+    }
+
+    // every tile is gathered again on the next `update`
+    pub fn invalidate(&mut self) {
+        self.dirty.iter_mut().for_each(|d| *d = true);
+        self.dirty_tiles = (0..self.dirty.len()).collect();
+    }
+
+    // narrows the bounds of the tiles written to since the last update to the depth they hold,
+    // `depth` has `samples` values per pixel
+    pub fn update(&mut self, depth: &[f32], width: usize, height: usize, samples: usize) {
+        for tile in self.dirty_tiles.drain(..) {
+            let (column, row) = (tile % self.columns, tile / self.columns);
+            let (x0, y0) = (column * self.tile_size, row * self.tile_size);
+            let (x1, y1) = ((x0 + self.tile_size).min(width), (y0 + self.tile_size).min(height));
+            let (mut min, mut max) = (f32::INFINITY, f32::NEG_INFINITY);
+            for y in y0..y1 {
+                for z in &depth[(y * width + x0) * samples..(y * width + x1) * samples] {
+                    min = min.min(*z);
+                    max = max.max(*z);
+                }
+            }
+            self.min[tile] = min;
+            self.max[tile] = max;
+            self.dirty[tile] = false;
+        }
+    }
+}
+
+// Whether a depth range is hidden behind everything stored in a tile of range `tile`.
+// Only compare functions that keep the nearest depth can tell that, and the stencil
+// has to be off, a stencil operation on a failed depth test needs every fragment.
+pub fn is_occluded(state: &DrawState, (near, far): (f32, f32), (tile_min, tile_max): (f32, f32)) -> bool {
+    if state.stencil.enabled {
+        return false;
+    }
+    match state.depth.compare {
+        CompareFunc::Less => near >= tile_max,
+        CompareFunc::LessEqual => near > tile_max,
+        CompareFunc::Greater => far <= tile_min,
+        CompareFunc::GreaterEqual => far < tile_min,
+        CompareFunc::Never => true,
+        _ => false,
+    }
+}
+
+// Whether a depth range passes the depth test everywhere in a tile of range `tile`
+pub fn is_visible(state: &DrawState, (near, far): (f32, f32), (tile_min, tile_max): (f32, f32)) -> bool {
+    match state.depth.compare {
+        CompareFunc::Less => far < tile_min,
+        CompareFunc::LessEqual => far <= tile_min,
+        CompareFunc::Greater => near > tile_max,
+        CompareFunc::GreaterEqual => near >= tile_max,
+        CompareFunc::Always => true,
+        _ => false,
+    }
+}
+
+impl Framebuffer {
+    fn hiz_bounds(&self, tile: usize) -> Option<(f32, f32)> {
+        Some(self.hiz.as_ref()?.bounds(tile))
+    }
+
+    // narrows the hierarchical depth to what was drawn, called before each draw
+    pub fn update_hiz(&mut self) {
+        let (width, height, samples) = (self.width, self.height, self.samples);
+        let depth = if samples > 1 { &self.sample_depth } else { &self.depth };
+        if let Some(hiz) = self.hiz.as_mut() {
+            hiz.update(depth, width, height, samples);
+        }
+    }
+
+    // Tiles of the screen rectangle `min` to `max` in which a depth range `depth` (nearest, farthest)
+    // would be entirely rejected, in rows from the top left tile. None without a hierarchical depth
+    // or when no tile can be rejected.
+    pub fn occluded_tiles(&self, state: &DrawState, min: Vec2, max: Vec2, depth: (f32, f32)) -> Option<OccludedTiles> {
+        let hiz = self.hiz.as_ref()?;
+        if state.stencil.enabled {
+            return None;
+        }
+        let (x0, y0, x1, y1) = hiz.tile_range(min, max)?;
+        let columns = x1 - x0 + 1;
+        let mut tiles = OccludedTiles {
+            tile_shift: hiz.tile_shift,
+            x0,
+            y0,
+            columns,
+            count: columns * (y1 - y0 + 1),
+            small: 0,
+            large: Vec::new(),
+        };
+        let mut any = false;
+        for row in y0..=y1 {
+            for column in x0..=x1 {
+                if is_occluded(state, depth, hiz.bounds(row * hiz.columns + column)) {
+                    tiles.insert((row - y0) * columns + column - x0);
+                    any = true;
+                }
+            }
+        }
+        any.then_some(tiles)
+    }
+
+    // depth in the range `depth` may be written to the pixels of the rectangle `min` to `max`
+    pub fn mark_depth_written(&mut self, min: Vec2, max: Vec2, depth: (f32, f32)) {
+        if let Some(hiz) = self.hiz.as_mut() {
+            if let Some(range) = hiz.tile_range(min, max) {
+                hiz.widen_range(range, depth);
+            }
+        }
+    }
+}
+
+// Result of `Framebuffer::occluded_tiles` for the tiles under a triangle.
+// Up to 64 tiles are bits of `small`, bigger triangles allocate `large`.
+#[derive(Debug, Clone)]
+pub struct OccludedTiles {
+    tile_shift: u32,
+    x0: usize,
+    y0: usize,
+    columns: usize,
+    count: usize,
+    small: u64,
+    large: Vec<bool>,
+}
+
+impl OccludedTiles {
+    fn insert(&mut self, tile: usize) {
+        if self.count <= 64 {
+            self.small |= 1 << tile;
+        } else {
+            if self.large.is_empty() {
+                self.large = vec![false; self.count];
+            }
+            self.large[tile] = true;
+        }
+    }
+
+    pub fn all(&self) -> bool {
+        if self.count <= 64 {
+            self.small.count_ones() as usize == self.count
+        } else {
+            !self.large.is_empty() && self.large.iter().all(|o| *o)
+        }
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        let (column, row) = ((x >> self.tile_shift).wrapping_sub(self.x0), (y >> self.tile_shift).wrapping_sub(self.y0));
+        let tile = row.wrapping_mul(self.columns).wrapping_add(column);
+        if column >= self.columns || tile >= self.count {
+            return false;
+        }
+        if self.count <= 64 { self.small & (1 << tile) != 0 } else { self.large.get(tile).copied().unwrap_or(false) }
+    }
+}
+
+// Occlusion query, how many samples of the screen rectangle around the projected box
+// would pass the depth test of `state` at the nearest depth of the box. With a hierarchical
+// depth, tiles the box is entirely in front of or behind are counted without their samples.
+// A box crossing the near plane is reported as fully visible.
+pub fn occlusion_query(target: &mut Framebuffer, aabb: &Aabb, mvp: &Mat4, state: &DrawState) -> usize {
+    if aabb.is_empty() {
+        return 0;
+    }
+    target.update_hiz();
+    let corners = (0..8).map(|i| {
+        let corner = glam::vec3(
+            if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+            if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+            if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+        );
+        *mvp * corner.extend(1.0)
+    });
+    let size = target.size();
+    let (mut min, mut max) = (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY));
+    let (mut near, mut far) = (f32::INFINITY, f32::NEG_INFINITY);
+    for clip in corners {
+        if near_plane_distance(clip, target.reverse_z) < 0.0 {
+            return target.width * target.height * target.samples;
+        }
+        let ndc = clip.xyz() / clip.w;
+        let screen = glam::vec2(
+            map_to_range(ndc.x, -1.0, 1.0, 0.0, size.x),
+            map_to_range(-ndc.y, -1.0, 1.0, 0.0, size.y),
+        );
+        min = min.min(screen);
+        max = max.max(screen);
+        near = near.min(ndc.z);
+        far = far.max(ndc.z);
+    }
+    let (x0, y0) = (min.x.max(0.0) as usize, min.y.max(0.0) as usize);
+    let (x1, y1) = ((max.x.min(size.x - 1.0)) as usize, (max.y.min(size.y - 1.0)) as usize);
+    if max.x < 0.0 || max.y < 0.0 || min.x > size.x - 1.0 || min.y > size.y - 1.0 {
+        return 0;
+    }
+    // the nearest point of the box stands for all of it
+    let z = if matches!(state.depth.compare, CompareFunc::Greater | CompareFunc::GreaterEqual) { far } else { near };
+    let samples = target.samples;
+    // without a hierarchical depth the whole rectangle is one tile
+    let tile_size = target.hiz.as_ref().map_or(target.width.max(target.height), |hiz| hiz.tile_size);
+    let mut passed = 0;
+    for row in y0 / tile_size..=y1 / tile_size {
+        for column in x0 / tile_size..=x1 / tile_size {
+            let (tx0, ty0) = ((column * tile_size).max(x0), (row * tile_size).max(y0));
+            let (tx1, ty1) = (((column + 1) * tile_size - 1).min(x1), ((row + 1) * tile_size - 1).min(y1));
+            let tile = target.hiz.as_ref().map(|hiz| row * hiz.columns + column);
+            if let Some(bounds) = tile.and_then(|tile| target.hiz_bounds(tile)) {
+                if is_occluded(state, (z, z), bounds) {
+                    continue;
+                }
+                if is_visible(state, (z, z), bounds) {
+                    passed += (tx1 - tx0 + 1) * (ty1 - ty0 + 1) * samples;
+                    continue;
+                }
+            }
+            let depth = if samples > 1 { &target.sample_depth } else { &target.depth };
+            for y in ty0..=ty1 {
+                let row = &depth[(y * target.width + tx0) * samples..(y * target.width + tx1 + 1) * samples];
+                passed += row.iter().filter(|stored| state.depth.compare.test(z, **stored)).count();
+            }
+        }
+    }
+    passed
+}
@@ -0,0 +1,117 @@
+use glam::{Quat, Vec3};
+use std::f32::consts::TAU;
+use std::path::Path;
+
+use crate::error::LoadError;
+use crate::transform::Transform;
+use crate::utils::import_gltf_buffers;
+
+// Copies of a mesh drawn in one call, `colors` is empty or has one tint per transform
+#[derive(Debug, Clone, Default)]
+pub struct Instances {
+    pub transforms: Vec<Transform>,
+    pub colors: Vec<Vec3>,
+}
+
+impl Instances {
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+
+    pub fn colors(&self) -> Option<&[Vec3]> {
+        (self.colors.len() == self.transforms.len()).then_some(self.colors.as_slice())
+    }
+
+    // `columns` by `rows` copies spaced by `spacing` on the XZ plane, around the origin,
+    // tinted with a hue going around the grid
+    pub fn grid(columns: usize, rows: usize, spacing: f32) -> Self {
+        let mut instances = Self::default();
+        let count = (columns * rows).max(1) as f32;
+        for row in 0..rows {
+            for column in 0..columns {
+                let offset = Vec3::new(column as f32 - (columns - 1) as f32 / 2.0, 0.0, row as f32 - (rows - 1) as f32 / 2.0);
+                instances.transforms.push(Transform::from_translation(offset * spacing));
+                let hue = (row * columns + column) as f32 / count * TAU;
+                let color = Vec3::new(hue.cos(), (hue + TAU / 3.0).cos(), (hue - TAU / 3.0).cos()) * 0.35 + 0.65;
+                instances.colors.push(color);
+            }
+        }
+        instances
+    }
+
+    // Transforms of the `EXT_mesh_gpu_instancing` extension of `node`, relative to the node.
+    // Missing attributes default to the identity, rotations may be normalized integers,
+    // other component types are an error. None when the node does not have the extension.
+    pub fn from_gltf(document: &gltf::Document, node: &gltf::Node, buffers: &[gltf::buffer::Data]) -> Result<Option<Self>, LoadError> {
+        let attributes = node
+            .extension_value("EXT_mesh_gpu_instancing")
+            .and_then(|extension| extension.get("attributes")?.as_object());
+        let Some(attributes) = attributes else {
+            return Ok(None);
+        };
+        let accessor = |name: &str| {
+            let index = attributes.get(name)?.as_u64()? as usize;
+            document.accessors().nth(index)
+        };
+        let get_buffer = |buffer: gltf::Buffer| Some(buffers.get(buffer.index())?.0.as_slice());
+
+        let vectors = |name: &str| -> Result<Option<Vec<Vec3>>, LoadError> {
+            let Some(accessor) = accessor(name) else {
+                return Ok(None);
+            };
+            if accessor.data_type() != gltf::accessor::DataType::F32 {
+                return Err(LoadError::Unsupported(format!("EXT_mesh_gpu_instancing {} not made of floats", name)));
+            }
+            Ok(gltf::accessor::Iter::<[f32; 3]>::new(accessor, get_buffer).map(|iter| iter.map(Vec3::from).collect()))
+        };
+        let translations = vectors("TRANSLATION")?;
+        let scales = vectors("SCALE")?;
+        let rotations: Option<Vec<Quat>> = match accessor("ROTATION") {
+            Some(accessor) => {
+                use gltf::accessor::DataType;
+                use gltf::animation::util::Rotations;
+                let rotations = match accessor.data_type() {
+                    DataType::F32 => gltf::accessor::Iter::new(accessor, get_buffer).map(Rotations::F32),
+                    DataType::I8 => gltf::accessor::Iter::new(accessor, get_buffer).map(Rotations::I8),
+                    DataType::I16 => gltf::accessor::Iter::new(accessor, get_buffer).map(Rotations::I16),
+                    data_type => {
+                        return Err(LoadError::Unsupported(format!("EXT_mesh_gpu_instancing ROTATION of {:?}", data_type)));
+                    }
+                };
+                rotations.map(|rotations| rotations.into_f32().map(Quat::from_array).collect())
+            }
+            None => None,
+        };
+
+        let count = [translations.as_ref().map(Vec::len), rotations.as_ref().map(Vec::len), scales.as_ref().map(Vec::len)]
+            .into_iter()
+            .flatten()
+            .max();
+        let Some(count) = count else {
+            return Ok(None);
+        };
+        let transforms = (0..count)
+            .map(|i| {
+                Transform::new(
+                    translations.as_ref().and_then(|t| t.get(i).copied()).unwrap_or(Vec3::ZERO),
+                    rotations.as_ref().and_then(|r| r.get(i).copied()).unwrap_or(Quat::IDENTITY),
+                    scales.as_ref().and_then(|s| s.get(i).copied()).unwrap_or(Vec3::ONE),
+                )
+            })
+            .collect();
+        Ok(Some(Self { transforms, colors: Vec::new() }))
+    }
+}
+
+// Instances of the node the viewer shows, empty for other formats or nodes without the extension
+pub fn load_instances(path: &Path) -> Result<Instances, LoadError> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    if !matches!(extension.as_str(), "gltf" | "glb") {
+        return Ok(Instances::default());
+    }
+    let (document, buffers) = import_gltf_buffers(path)?;
+    let Some(node) = document.scenes().flat_map(|scene| scene.nodes()).find(|node| node.mesh().is_some()) else {
+        return Ok(Instances::default());
+    };
+    Ok(Instances::from_gltf(&document, &node, &buffers)?.unwrap_or_default())
+}
@@ -0,0 +1,156 @@
+use glam::{Mat4, Vec3};
+use std::f32::consts::TAU;
+
+use crate::camera::Camera;
+use crate::material::Material;
+use crate::utils::{from_argb8_v, to_argb8_v};
+
+// How lit draws are shaded, the viewer cycles through them
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Shading {
+    // texture and vertex colors only
+    Unlit,
+    // every fragment is lit as it is drawn
+    Forward,
+    // surfaces go into a G-buffer, only the visible ones are lit
+    Deferred,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightKind {
+    // parallel rays going along the direction
+    Directional(Vec3),
+    // fades out to nothing at `range`
+    Point { position: Vec3, range: f32 },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: Vec3,
+    pub intensity: f32,
+}
+
+impl Light {
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self { kind: LightKind::Directional(direction.normalize_or_zero()), color, intensity }
+    }
+
+    pub fn point(position: Vec3, range: f32, color: Vec3, intensity: f32) -> Self {
+        Self { kind: LightKind::Point { position, range }, color, intensity }
+    }
+
+    // direction towards the light and its radiance at `position`, none out of its reach
+    fn incoming(&self, position: Vec3) -> Option<(Vec3, Vec3)> {
+        let (direction, attenuation) = match self.kind {
+            LightKind::Directional(direction) => (-direction, 1.0),
+            LightKind::Point { position: light, range } => {
+                let offset = light - position;
+                let distance = offset.length();
+                if distance >= range || distance == 0.0 {
+                    return None;
+                }
+                // inverse square, windowed to reach zero at the range
+                let window = (1.0 - (distance / range).powi(4)).max(0.0).powi(2);
+                (offset / distance, window / (1.0 + distance * distance))
+            }
+        };
+        Some((direction, self.color * self.intensity * attenuation))
+    }
+}
+
+// Material parameters of a lit surface, the albedo comes from the texture and the vertex colors
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Surface {
+    pub specular: f32,
+    pub shininess: f32,
+}
+
+impl Default for Surface {
+    fn default() -> Self {
+        Self { specular: 0.25, shininess: 32.0 }
+    }
+}
+
+impl Surface {
+    // `Ks` and `Ns`, materials without a specular color keep the default highlight
+    pub fn from_material(material: &Material) -> Self {
+        if material.specular == Vec3::ZERO {
+            return Self::default();
+        }
+        Self {
+            specular: material.specular.max_element(),
+            shininess: material.shininess.max(1.0),
+        }
+    }
+}
+
+// Lights of a frame and the camera they are seen from, Blinn-Phong shading in world space
+#[derive(Debug, Clone)]
+pub struct Lighting {
+    pub lights: Vec<Light>,
+    pub ambient: Vec3,
+    pub eye: Vec3,
+    // from NDC back to world space
+    pub inverse_view_projection: Mat4,
+}
+
+impl Lighting {
+    pub fn new(camera: &Camera, lights: Vec<Light>, ambient: Vec3) -> Self {
+        Self {
+            lights,
+            ambient,
+            eye: camera.transform.translation,
+            inverse_view_projection: (camera.projection() * camera.view()).inverse(),
+        }
+    }
+
+    // world position of the center of pixel `x`, `y` of a `width` by `height` target at depth `z`
+    pub fn position(&self, x: usize, y: usize, z: f32, width: usize, height: usize) -> Vec3 {
+        let ndc = glam::vec3(
+            (x as f32 + 0.5) / width as f32 * 2.0 - 1.0,
+            1.0 - (y as f32 + 0.5) / height as f32 * 2.0,
+            z,
+        );
+        self.inverse_view_projection.project_point3(ndc)
+    }
+
+    // color of `albedo` (0.0 to 1.0) lit by every light, surfaces facing away are lit from behind
+    pub fn shade(&self, albedo: Vec3, normal: Vec3, position: Vec3, surface: &Surface) -> Vec3 {
+        let view = (self.eye - position).normalize_or_zero();
+        let normal = if normal.dot(view) < 0.0 { -normal } else { normal };
+        let mut diffuse = self.ambient;
+        let mut specular = Vec3::ZERO;
+        for light in &self.lights {
+            let Some((direction, radiance)) = light.incoming(position) else {
+                continue;
+            };
+            let n_dot_l = normal.dot(direction);
+            if n_dot_l <= 0.0 {
+                continue;
+            }
+            diffuse += radiance * n_dot_l;
+            let half = (direction + view).normalize_or_zero();
+            specular += radiance * normal.dot(half).max(0.0).powf(surface.shininess) * surface.specular;
+        }
+        albedo * diffuse + specular
+    }
+
+    // `shade` of an ARGB color, the alpha is kept
+    pub fn shade_argb(&self, albedo: u32, normal: Vec3, position: Vec3, surface: &Surface) -> u32 {
+        let color = self.shade(from_argb8_v(albedo) / 255.0, normal, position, surface);
+        to_argb8_v((albedo >> 24) as u8, (color * 255.0).clamp(Vec3::ZERO, Vec3::splat(255.0)))
+    }
+}
+
+// the lights of the viewer, a key light and `count` colored point lights on a ring around the origin
+pub fn light_ring(count: usize, radius: f32, time: f32) -> Vec<Light> {
+    let mut lights = vec![Light::directional(glam::vec3(-0.4, -1.0, -0.6), Vec3::ONE, 0.6)];
+    for i in 0..count {
+        let angle = i as f32 / count as f32 * TAU + time * 0.5;
+        let color = Vec3::new(angle.cos(), (angle + TAU / 3.0).cos(), (angle - TAU / 3.0).cos()) * 0.5 + 0.5;
+        let position = glam::vec3(angle.cos() * radius, (angle * 3.0).sin() * 0.5, angle.sin() * radius);
+        lights.push(Light::point(position, radius * 2.0, color, 3.0));
+    }
+    lights
+}
@@ -0,0 +1,281 @@
+use glam::{Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
+
+use crate::draw_state::DrawState;
+use crate::framebuffer::Framebuffer;
+use crate::geometry::Vertex;
+use crate::raster::{draw_fragment, near_plane_distance};
+use crate::texture::Texture;
+use crate::utils::{map_to_range, modulate_argb8};
+
+// Vertex after the perspective divide, mapped to the window.
+// Every field is affine in screen space, so they can be interpolated linearly.
+#[derive(Debug, Copy, Clone)]
+pub struct ScreenVertex {
+    pub pos: Vec2,
+    pub z: f32,
+    pub rec: f32, // 1/w
+    pub uv: Vec2, // divided by w
+    pub c: Vec3,  // divided by w
+}
+
+impl ScreenVertex {
+    pub fn from_clip(clip: Vec4, uv: Vec2, c: Vec3, viewport_size: Vec2) -> Self {
+        let rec = 1.0 / clip.w;
+        let ndc = clip.xyz() * rec;
+        Self {
+            pos: glam::vec2(
+                map_to_range(ndc.x, -1.0, 1.0, 0.0, viewport_size.x),
+                map_to_range(-ndc.y, -1.0, 1.0, 0.0, viewport_size.y),
+            ),
+            z: ndc.z,
+            rec,
+            uv: uv * rec,
+            c: c * rec,
+        }
+    }
+
+    pub fn lerp(self, other: Self, t: f32) -> Self {
+        Self {
+            pos: self.pos.lerp(other.pos, t),
+            z: self.z + (other.z - self.z) * t,
+            rec: self.rec + (other.rec - self.rec) * t,
+            uv: self.uv.lerp(other.uv, t),
+            c: self.c.lerp(other.c, t),
+        }
+    }
+
+    // perspective correct uv
+    pub fn uv(&self) -> Vec2 {
+        self.uv / self.rec
+    }
+
+    // perspective correct vertex color
+    pub fn color(&self) -> Vec3 {
+        self.c / self.rec
+    }
+}
+
+// Liang-Barsky clipping of the segment against a rectangle, returns the parameter range inside
+fn clip_to_rect(p0: Vec2, p1: Vec2, min: Vec2, max: Vec2) -> Option<(f32, f32)> {
+    let d = p1 - p0;
+    let (mut t0, mut t1) = (0.0_f32, 1.0_f32);
+    for (p, q) in [
+        (-d.x, p0.x - min.x),
+        (d.x, max.x - p0.x),
+        (-d.y, p0.y - min.y),
+        (d.y, max.y - p0.y),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else {
+            let t = q / p;
+            if p < 0.0 {
+                t0 = t0.max(t);
+            } else {
+                t1 = t1.min(t);
+            }
+        }
+    }
+    if t0 > t1 { None } else { Some((t0, t1)) }
+}
+
+pub fn raster_line(
+    v0: &Vertex,
+    v1: &Vertex,
+    mvp: &Mat4,
+    texture: &Texture,
+    state: &DrawState,
+    target: &mut Framebuffer,
+) {
+    let mut clip0 = *mvp * v0.pos.extend(1.0);
+    let mut clip1 = *mvp * v1.pos.extend(1.0);
+    let (mut uv0, mut uv1) = (v0.uv, v1.uv);
+    let (mut c0, mut c1) = (v0.c, v1.c);
+
+    let d0 = near_plane_distance(clip0, target.reverse_z);
+    let d1 = near_plane_distance(clip1, target.reverse_z);
+    if d0 < 0.0 && d1 < 0.0 {
+        return;
+    }
+    if d0 < 0.0 {
+        let t = d0 / (d0 - d1);
+        clip0 = clip0.lerp(clip1, t);
+        uv0 = uv0.lerp(uv1, t);
+        c0 = c0.lerp(c1, t);
+    } else if d1 < 0.0 {
+        let t = d1 / (d1 - d0);
+        clip1 = clip1.lerp(clip0, t);
+        uv1 = uv1.lerp(uv0, t);
+        c1 = c1.lerp(c0, t);
+    }
+
+    let viewport_size = target.size();
+    let s0 = ScreenVertex::from_clip(clip0, uv0, c0, viewport_size);
+    let s1 = ScreenVertex::from_clip(clip1, uv1, c1, viewport_size);
+    draw_line(target, state, texture, s0, s1);
+}
+
+pub fn draw_line(
+    target: &mut Framebuffer,
+    state: &DrawState,
+    texture: &Texture,
+    s0: ScreenVertex,
+    s1: ScreenVertex,
+) {
+    // keep only the part around the viewport so huge coordinates do not stall the loops
+    let margin = Vec2::splat(state.line_width + 1.0);
+    let Some((t0, t1)) = clip_to_rect(s0.pos, s1.pos, -margin, target.size() + margin) else {
+        return;
+    };
+    let (mut s0, mut s1) = (s0.lerp(s1, t0), s0.lerp(s1, t1));
+    // lines have no slope, only the constant bias applies
+    s0.z += state.depth.offset(0.0);
+    s1.z += state.depth.offset(0.0);
+
+    if state.line_smooth {
+        draw_line_smooth(target, state, texture, s0, s1);
+    } else {
+        draw_line_bresenham(target, state, texture, s0, s1);
+    }
+}
+
+fn shade(texture: &Texture, v: ScreenVertex) -> u32 {
+    let uv = v.uv();
+    modulate_argb8(texture.argb_at_uv(uv.x, uv.y), v.color())
+}
+
+// Bresenham's algorithm, wide lines are extended along the minor axis
+fn draw_line_bresenham(
+    target: &mut Framebuffer,
+    state: &DrawState,
+    texture: &Texture,
+    s0: ScreenVertex,
+    s1: ScreenVertex,
+) {
+    let (x0, y0) = (s0.pos.x.floor() as i64, s0.pos.y.floor() as i64);
+    let (x1, y1) = (s1.pos.x.floor() as i64, s1.pos.y.floor() as i64);
+
+    let dx = (x1 - x0).abs();
+    let dy = -(y1 - y0).abs();
+    let sx = if x0 < x1 { 1 } else { -1 };
+    let sy = if y0 < y1 { 1 } else { -1 };
+    let steep = -dy > dx;
+    let steps = dx.max(-dy).max(1) as f32;
+
+    let width = state.line_width.round().max(1.0) as i64;
+    let low = -(width - 1) / 2;
+    let high = low + width - 1;
+
+    let (mut x, mut y) = (x0, y0);
+    let mut err = dx + dy;
+    let mut step = 0;
+    loop {
+        let v = s0.lerp(s1, step as f32 / steps);
+        for offset in low..=high {
+            let (px, py) = if steep { (x + offset, y) } else { (x, y + offset) };
+            if px >= 0 && py >= 0 {
+                draw_fragment(target, state, px as usize, py as usize, v.z, 1.0, || shade(texture, v));
+            }
+        }
+
+        if x == x1 && y == y1 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+            if !steep { step += 1; }
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+            if steep { step += 1; }
+        }
+    }
+}
+
+// Xiaolin Wu style line, the coverage of every pixel is the overlap with a box of `line_width`
+fn draw_line_smooth(
+    target: &mut Framebuffer,
+    state: &DrawState,
+    texture: &Texture,
+    mut s0: ScreenVertex,
+    mut s1: ScreenVertex,
+) {
+    let steep = (s1.pos.y - s0.pos.y).abs() > (s1.pos.x - s0.pos.x).abs();
+    // (major, minor) coordinates
+    let axes = |p: Vec2| if steep { glam::vec2(p.y, p.x) } else { p };
+    if axes(s0.pos).x > axes(s1.pos).x {
+        std::mem::swap(&mut s0, &mut s1);
+    }
+    let (p0, p1) = (axes(s0.pos), axes(s1.pos));
+
+    let length = p1.x - p0.x;
+    let gradient = if length > 0.0 { (p1.y - p0.y) / length } else { 0.0 };
+    // the width is measured perpendicular to the line
+    let half = state.line_width.max(1.0) * 0.5 * (1.0 + gradient * gradient).sqrt();
+
+    let first = (p0.x - 0.5).floor() as i64;
+    let last = (p1.x + 0.5).floor() as i64;
+    for major in first..=last {
+        let (start, end) = (major as f32, major as f32 + 1.0);
+        // half pixel caps at both ends
+        let major_coverage = (end.min(p1.x + 0.5) - start.max(p0.x - 0.5)).clamp(0.0, 1.0);
+        if major_coverage <= 0.0 {
+            continue;
+        }
+
+        let center = start + 0.5;
+        let t = if length > 0.0 { ((center - p0.x) / length).clamp(0.0, 1.0) } else { 0.0 };
+        let v = s0.lerp(s1, t);
+
+        let minor = p0.y + gradient * (center.clamp(p0.x, p1.x) - p0.x);
+        let (low, high) = (minor - half, minor + half);
+        for row in low.floor() as i64..=high.floor() as i64 {
+            let coverage = ((row as f32 + 1.0).min(high) - (row as f32).max(low)).clamp(0.0, 1.0) * major_coverage;
+            let (px, py) = if steep { (row, major) } else { (major, row) };
+            if px >= 0 && py >= 0 {
+                draw_fragment(target, state, px as usize, py as usize, v.z, coverage, || shade(texture, v));
+            }
+        }
+    }
+}
+
+// Square point sprite of `point_size` pixels, round with `line_smooth`
+pub fn raster_point(
+    v: &Vertex,
+    mvp: &Mat4,
+    texture: &Texture,
+    state: &DrawState,
+    target: &mut Framebuffer,
+) {
+    let clip = *mvp * v.pos.extend(1.0);
+    if near_plane_distance(clip, target.reverse_z) < 0.0 {
+        return;
+    }
+    let mut s = ScreenVertex::from_clip(clip, v.uv, v.c, target.size());
+    s.z += state.depth.offset(0.0);
+
+    let half = state.point_size.max(1.0) * 0.5;
+    let min = (s.pos - Vec2::splat(half)).floor().max(Vec2::ZERO);
+    let max = (s.pos + Vec2::splat(half)).ceil().min(target.size());
+
+    for y in min.y as usize..max.y as usize {
+        for x in min.x as usize..max.x as usize {
+            let d = glam::vec2(x as f32 + 0.5, y as f32 + 0.5) - s.pos;
+            let coverage = if state.line_smooth {
+                (half - d.length() + 0.5).clamp(0.0, 1.0)
+            } else if d.x.abs() <= half && d.y.abs() <= half {
+                1.0
+            } else {
+                0.0
+            };
+
+            let uv = if state.point_sprite { d / (2.0 * half) + Vec2::splat(0.5) } else { s.uv() };
+            draw_fragment(target, state, x, y, s.z, coverage, || modulate_argb8(texture.argb_at_uv(uv.x, uv.y), v.c));
+        }
+    }
+}
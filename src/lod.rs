@@ -0,0 +1,201 @@
+use glam::Mat4;
+use std::path::Path;
+
+use crate::bounds::BoundingSphere;
+use crate::error::{LoadError, Warnings};
+use crate::geometry::Mesh;
+use crate::material::Material;
+use crate::normals::NormalMode;
+use crate::utils::{first_mesh_node, import_gltf, load_mesh};
+
+// switching to another level needs the size to be this much past the threshold
+pub const DEFAULT_HYSTERESIS: f32 = 0.1;
+// vertices this close are merged before generating the levels
+const WELD_TOLERANCE: f32 = 1e-5;
+
+#[derive(Debug, Clone)]
+pub struct LodLevel {
+    pub mesh: Mesh,
+    // the level is used while the projected bounding sphere is at least this big,
+    // relative to the height of the viewport
+    pub min_screen_size: f32,
+    // triangles the level was simplified to, it may not have got that far
+    pub target_triangles: usize,
+}
+
+// Levels of detail of a mesh, from the most detailed to the coarsest
+#[derive(Debug, Clone)]
+pub struct LodMesh {
+    levels: Vec<LodLevel>,
+    pub hysteresis: f32,
+    // below this size nothing is drawn
+    pub cull_screen_size: f32,
+    current: Option<usize>,
+    // of the most detailed level
+    bounding_sphere: BoundingSphere,
+}
+
+impl LodMesh {
+    pub fn new(mut levels: Vec<LodLevel>) -> Self {
+        levels.sort_by(|a, b| b.min_screen_size.total_cmp(&a.min_screen_size));
+        let bounding_sphere = levels.first().map_or(BoundingSphere::default(), |level| *level.mesh.bounding_sphere());
+        Self {
+            levels,
+            hysteresis: DEFAULT_HYSTERESIS,
+            cull_screen_size: 0.0,
+            current: Some(0),
+            bounding_sphere,
+        }
+    }
+
+    // Up to `count` levels, each one simplified to half of the triangles of the previous one
+    // and used when the mesh covers half the size. The coarser levels start from a welded copy
+    // with smooth normals, so the corners of meshes with a normal per face are merged
+    // too, only hard edges stay split. Stops early when the simplification gets stuck on borders.
+    pub fn generate(mesh: Mesh, count: usize) -> Self {
+        let mut welded = mesh.clone();
+        welded.compute_normals(NormalMode::default());
+        welded.weld(WELD_TOLERANCE);
+
+        let target_triangles = mesh.triangles().len();
+        let mut levels = vec![LodLevel { mesh, min_screen_size: 0.5, target_triangles }];
+        for level in 1..count {
+            let previous = levels.last().unwrap();
+            let mut mesh = if level == 1 { welded.clone() } else { previous.mesh.clone() };
+            let target_triangles = previous.target_triangles / 2;
+            mesh.simplify(target_triangles);
+            if mesh.triangles().len() >= previous.mesh.triangles().len() {
+                break;
+            }
+            let min_screen_size = previous.min_screen_size / 2.0;
+            levels.push(LodLevel { mesh, min_screen_size, target_triangles });
+        }
+        levels.last_mut().unwrap().min_screen_size = 0.0;
+        Self::new(levels)
+    }
+
+    // Levels of a node with the `MSFT_lod` extension, the coarser ones are the nodes in `ids`.
+    // Their thresholds come from `MSFT_screencoverage` in the extras of the node, one more
+    // than there are levels being the size at which the mesh is culled.
+    // None when the node has no mesh or no levels, a level that is not a mesh node is an error.
+    pub fn from_gltf(
+        document: &gltf::Document,
+        node: &gltf::Node,
+        buffers: &[gltf::buffer::Data],
+        materials: &[Material],
+    ) -> Result<Option<Self>, LoadError> {
+        let ids = node.extension_value("MSFT_lod").and_then(|lod| lod.get("ids")?.as_array());
+        let (Some(ids), Some(mesh)) = (ids, node.mesh()) else {
+            return Ok(None);
+        };
+
+        let mut meshes = vec![mesh];
+        for id in ids.iter().filter_map(|id| id.as_u64().map(|id| id as usize)) {
+            let mesh = document.nodes().nth(id).and_then(|n| n.mesh());
+            meshes.push(mesh.ok_or_else(|| LoadError::Invalid(format!("MSFT_lod node #{} has no mesh", id)))?);
+        }
+
+        let coverages: Vec<f32> = node
+            .extras()
+            .as_ref()
+            .and_then(|extras| gltf::json::deserialize::from_str::<gltf::json::Value>(extras.get()).ok())
+            .and_then(|extras| {
+                let coverages = extras.get("MSFT_screencoverage")?.as_array()?;
+                Some(coverages.iter().filter_map(|c| c.as_f64().map(|c| c as f32)).collect())
+            })
+            .unwrap_or_default();
+
+        let count = meshes.len();
+        let levels = meshes
+            .iter()
+            .enumerate()
+            .map(|(i, mesh)| {
+                let mesh = Mesh::load_from_gltf(mesh, buffers, materials);
+                LodLevel {
+                    target_triangles: mesh.triangles().len(),
+                    mesh,
+                    // without coverages every level takes half the size of the previous one
+                    min_screen_size: coverages
+                        .get(i)
+                        .copied()
+                        .unwrap_or(if i + 1 == count { 0.0 } else { 0.5f32.powi(i as i32 + 1) }),
+                }
+            })
+            .collect();
+        let mut lod = Self::new(levels);
+        lod.cull_screen_size = coverages.get(count).copied().unwrap_or(0.0);
+        Ok(Some(lod))
+    }
+
+    pub fn levels(&self) -> &Vec<LodLevel> {
+        &self.levels
+    }
+
+    // index of the level picked by the last `select`, none when the mesh is culled
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn mesh(&self) -> Option<&Mesh> {
+        self.current.map(|level| &self.levels[level].mesh)
+    }
+
+    // height of the projected bounding sphere relative to the height of the viewport
+    pub fn screen_size(&self, model: &Mat4, view: &Mat4, projection: &Mat4) -> f32 {
+        let sphere = self.bounding_sphere.transform(&(*view * *model));
+        let distance = -sphere.center.z;
+        if distance <= sphere.radius {
+            return f32::INFINITY;
+        }
+        // the projection maps a height of 1 at distance 1 to half of the viewport
+        sphere.radius * projection.y_axis.y / distance
+    }
+
+    fn level_for(&self, screen_size: f32) -> Option<usize> {
+        if screen_size < self.cull_screen_size {
+            return None;
+        }
+        let level = self.levels.iter().position(|l| screen_size >= l.min_screen_size);
+        Some(level.unwrap_or(self.levels.len().saturating_sub(1)))
+    }
+
+    // Picks the level for `screen_size`, returns true when it is not the one picked before.
+    // A change of level only happens once the size is `hysteresis` past the threshold,
+    // so a mesh near a threshold does not keep popping.
+    pub fn select(&mut self, screen_size: f32) -> bool {
+        if self.levels.is_empty() {
+            return false;
+        }
+        // culled is the coarsest level of all
+        let rank = |level: Option<usize>| level.unwrap_or(usize::MAX);
+        let ideal = self.level_for(screen_size);
+        let level = if rank(ideal) > rank(self.current) {
+            self.level_for(screen_size * (1.0 + self.hysteresis))
+        } else if rank(ideal) < rank(self.current) {
+            self.level_for(screen_size * (1.0 - self.hysteresis))
+        } else {
+            ideal
+        };
+        let changed = level != self.current;
+        self.current = level;
+        changed
+    }
+}
+
+// glTF files with `MSFT_lod` bring their own levels, `count` levels are generated for everything else
+pub fn load_lod_mesh(path: &Path, count: usize) -> Result<(LodMesh, Vec<Material>, Warnings), LoadError> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    if matches!(extension.as_str(), "gltf" | "glb") {
+        let (document, buffers, materials, warnings) = import_gltf(path)?;
+        let Some(node) = first_mesh_node(&document) else {
+            return Ok((LodMesh::generate(Mesh::new(), count), materials, warnings));
+        };
+        if let Some(lod) = LodMesh::from_gltf(&document, &node, &buffers, &materials)? {
+            return Ok((lod, materials, warnings));
+        }
+        let mesh = node.mesh().map_or_else(Mesh::new, |mesh| Mesh::load_from_gltf(&mesh, &buffers, &materials));
+        return Ok((LodMesh::generate(mesh, count), materials, warnings));
+    }
+    let (mesh, materials, warnings) = load_mesh(path)?;
+    Ok((LodMesh::generate(mesh, count), materials, warnings))
+}
@@ -1,9 +1,9 @@
 use geometry::Mesh;
-use glam::{Vec2, /*Vec3, */Vec4, Mat4};
+use glam::{Vec2, Vec3, Vec4, Mat4};
 //use glam::Vec3Swizzles;
 use glam::Vec4Swizzles;
 //use gltf::json::extensions::mesh;
-use minifb::{Key, Window, WindowOptions, MouseMode};
+use minifb::{Key, KeyRepeat, Window, WindowOptions, MouseButton, MouseMode};
 use std::path::Path;
 
 use std::time::{/*Duration,*/ Instant};
@@ -12,46 +12,174 @@ use std::time::{/*Duration,*/ Instant};
 pub mod geometry;
 pub use geometry::Vertex;
 pub use geometry::Triangle;
+pub use geometry::Topology;
+pub mod normals;
+pub use normals::NormalMode;
+pub mod primitives;
+pub mod processing;
+pub mod lod;
+pub mod bounds;
+pub use bounds::{Aabb, BoundingSphere, Frustum};
+pub mod bvh;
+pub use bvh::{Bvh, Hit};
+pub use lod::LodMesh;
+pub mod morph;
+pub use morph::MorphTarget;
+pub mod skinning;
+pub use skinning::{JointWeights, NodeTree, Skin, SkinningMethod};
+pub mod animation;
+pub use animation::{Animation, Playback, Rig};
+pub mod instancing;
+pub mod vertex_stage;
+pub mod hiz;
+pub use hiz::{HiZ, OccludedTiles};
+pub mod lighting;
+pub use lighting::{Light, LightKind, Lighting, Shading, Surface};
+pub mod deferred;
+pub use deferred::GBuffer;
+pub mod debug_view;
+pub use debug_view::{DebugMode, DebugView};
+use vertex_stage::PostTransform;
+pub use instancing::Instances;
 pub mod texture;
 pub use texture::Texture;
 pub mod camera;
 pub use camera::Camera;
 pub mod transform;
 pub use transform::Transform;
+pub mod framebuffer;
+pub use framebuffer::Framebuffer;
+pub mod transparency;
+pub use transparency::{BlendMode, ABuffer, Fragment};
+pub mod draw_state;
+pub use draw_state::{DrawState, DepthState, StencilState, StencilOp, CompareFunc, PolygonMode};
+pub mod antialiasing;
+pub use antialiasing::AntiAliasing;
+pub mod error;
+pub use error::{LoadError, Warnings};
+pub mod post_processing;
+pub use post_processing::{PostStack, PostEffect};
+pub mod depth_effects;
+pub mod material;
+pub use material::Material;
+pub mod obj;
+pub mod export;
+pub mod ply;
+pub mod stl;
+pub mod raster;
+pub use raster::{draw_fragment, near_plane_distance, uses_a_buffer, write_color, write_hdr};
+pub mod lines;
+use lines::{raster_line, raster_point};
 
 
 pub mod utils;
-use utils::{coords_to_index, map_to_range, /*cofactor,*/ load_gltf};
+use utils::{coords_to_index, map_to_range, /*cofactor,*/ alpha_of_argb8, from_argb8_v, modulate_argb8};
 
 const MESH_PATH: &str = "assets/helmet.gltf";
 const TEXT_PATH: &str = "assets/albedo.jpg";
+const LUT_PATH: &str = "assets/warm.cube";
 
 const WIDTH: usize = 500;
 const HEIGHT: usize = 500;
 const WIDTH_F: f32 = HEIGHT as f32;
 const HEIGHT_F: f32 = HEIGHT as f32;
 
+// size of the selection outline relative to the mesh
+const OUTLINE_SCALE: f32 = 1.03;
+// in units of `DepthState::BIAS_UNIT`
+const WIREFRAME_BIAS: f32 = 2048.0;
 
-pub fn clear_buffer(buffer: &mut Vec<u32>) {
-    let length = buffer.len();
-    *buffer = vec![0; length];
-}
+// levels of detail generated for meshes that do not bring their own
+const LOD_LEVELS: usize = 4;
+
+// 8 fragment layers of the A-buffer at 500x500
+const A_BUFFER_BUDGET: usize = 40 * 1024 * 1024;
+
+// point lights circling the model when it is lit, besides the key light
+const LIGHT_COUNT: usize = 8;
+const LIGHT_RING_RADIUS: f32 = 2.0;
+const AMBIENT: Vec3 = Vec3::splat(0.15);
 
-pub fn clear_z_buffer(buffer: &mut Vec<f32>) {
-    let length = buffer.len();
-    *buffer = vec![1.0; length];
-}
 
 // Area of paralellogram
 pub fn get_doubled_triangle_area(v0: glam::Vec2, v1: glam::Vec2, v2: glam::Vec2) -> f32 {
-    let area = ((v1.x - v0.x) * (v2.y - v0.y)) - ((v1.y - v0.y) * (v2.x - v0.x));
-    area
+    ((v1.x - v0.x) * (v2.y - v0.y)) - ((v1.y - v0.y) * (v2.x - v0.x))
+}
+
+// perspective correct texture lookup for the barycentric coordinates `w`
+#[allow(clippy::too_many_arguments)]
+pub fn shade_fragment(
+    w: Vec3,
+    v0: Vertex,
+    v1: Vertex,
+    v2: Vertex,
+    rec0: f32,
+    rec1: f32,
+    rec2: f32,
+    texture: &Texture) -> u32
+{
+    let correction = w.x * rec0 + w.y * rec1 + w.z * rec2;
+    // 1/(1/z) = z
+    let correction = 1.0 / correction;
+
+    let mut tex_coords = w.x * v0.uv + w.y * v1.uv + w.z * v2.uv;
+    tex_coords *= correction;
+    let color = (w.x * v0.c + w.y * v1.c + w.z * v2.c) * correction;
+    modulate_argb8(texture.argb_at_uv(tex_coords.x, tex_coords.y), color)
+}
+
+// perspective correct normal for the barycentric coordinates `w`, unit length
+pub fn interpolate_normal(w: Vec3, v0: Vertex, v1: Vertex, v2: Vertex, rec0: f32, rec1: f32, rec2: f32) -> Vec3 {
+    let correction = 1.0 / (w.x * rec0 + w.y * rec1 + w.z * rec2);
+    ((w.x * v0.normal + w.y * v1.normal + w.z * v2.normal) * correction).normalize_or_zero()
+}
+
+// perspective correct attributes of every vertex for the barycentric coordinates `w`
+pub fn interpolate_vertex(w: Vec3, v0: Vertex, v1: Vertex, v2: Vertex, rec0: f32, rec1: f32, rec2: f32) -> Vertex {
+    (v0 * w.x + v1 * w.y + v2 * w.z) * (1.0 / (w.x * rec0 + w.y * rec1 + w.z * rec2))
+}
+
+// Debug color of pixel `index`, the alternate fragment path of `draw_pixel` and `draw_triangle_msaa`.
+// `w` are the barycentric coordinates at the fragment, one pixel to the right and one pixel down.
+#[allow(clippy::too_many_arguments)]
+pub fn debug_fragment(
+    debug_view: &mut DebugView,
+    index: usize,
+    w: [Vec3; 3],
+    v0: Vertex,
+    v1: Vertex,
+    v2: Vertex,
+    rec0: f32,
+    rec1: f32,
+    rec2: f32,
+    texture: &Texture) -> u32
+{
+    let [vertex, right, down] = w.map(|w| interpolate_vertex(w, v0, v1, v2, rec0, rec1, rec2));
+    debug_view.shade(index, &vertex, right.uv - vertex.uv, down.uv - vertex.uv, texture)
+}
+
+// Lit fragment of pixel `index` at depth `z`. Opaque ones are stored in the G-buffer when there is one
+// and none is returned, the others are shaded with the lights of the target right away.
+pub fn light_fragment(target: &mut Framebuffer, state: &DrawState, index: usize, z: f32, albedo: u32, normal: Vec3) -> Option<u32> {
+    if state.blend == BlendMode::Opaque {
+        if let Some(g_buffer) = target.g_buffer.as_mut() {
+            g_buffer.write(index, albedo, normal, state.surface, state.intensity, z);
+            return None;
+        }
+    }
+    let Some(lighting) = target.lighting.as_ref() else {
+        return Some(albedo);
+    };
+    let (x, y) = (index % target.width, index / target.width);
+    let position = lighting.position(x, y, z, target.width, target.height);
+    Some(lighting.shade_argb(albedo, normal, position, &state.surface))
 }
 
 // main function which draws the color of pixels
+#[allow(clippy::too_many_arguments)]
 pub fn draw_pixel(
-    buffer: &mut Vec<u32>,
-    z_buffer: &mut Vec<f32>,
+    target: &mut Framebuffer,
+    state: &DrawState,
     index: usize,
     x: f32, y: f32,
     sc0: Vec2,
@@ -73,21 +201,187 @@ pub fn draw_pixel(
 
     let z = w0 * v0.pos.z + w1 * v1.pos.z + w2 * v2.pos.z;
 
-    let mut _a = 0;
+    if !state.depth_stencil_test(&mut target.stencil[index], z, target.depth[index]) { return; }
 
-    if z_buffer[index] < z { return; }
+    // depth and stencil only pass, no need to shade
+    if !state.color_write {
+        if state.depth.write { target.depth[index] = z; }
+        return;
+    }
 
-    z_buffer[index] = z;
+    // debug colors are neither lit nor blended
+    if let Some(debug_view) = target.debug_view.as_mut() {
+        let barycentric = |p: Vec2| glam::vec3(
+            get_doubled_triangle_area(p,   sc1, sc2) * reversed_global_area,
+            get_doubled_triangle_area(sc0, p,   sc2) * reversed_global_area,
+            get_doubled_triangle_area(sc0, sc1, p  ) * reversed_global_area,
+        );
+        let w = [p, p + Vec2::X, p + Vec2::Y].map(barycentric);
+        let color = debug_fragment(debug_view, index, w, v0, v1, v2, rec0, rec1, rec2, texture);
+        let state = DrawState { blend: BlendMode::Opaque, ..*state };
+        write_color(&state, &mut target.color[index], &mut target.depth[index], z, color);
+        return;
+    }
 
-    let correction = w0 * rec0 + w1 * rec1 + w2 * rec2;
-    // 1/(1/z) = z
-    let correction = 1.0 / correction;
+    let mut color = shade_fragment(glam::vec3(w0, w1, w2), v0, v1, v2, rec0, rec1, rec2, texture);
 
-    let mut tex_coords = w0 * v0.uv + w1 * v1.uv + w2 * v2.uv;
-    tex_coords *= correction;
-    let color = texture.argb_at_uv(tex_coords.x, tex_coords.y);
+    if state.lit && (target.lighting.is_some() || target.g_buffer.is_some()) {
+        let normal = interpolate_normal(glam::vec3(w0, w1, w2), v0, v1, v2, rec0, rec1, rec2);
+        let Some(lit) = light_fragment(target, state, index, z, color, normal) else {
+            if state.depth.write { target.depth[index] = z; }
+            return;
+        };
+        color = lit;
+    }
+
+    if uses_a_buffer(state, target) {
+        if let Some(a_buffer) = target.a_buffer.as_mut() {
+            a_buffer.append(index, Fragment {
+                color: from_argb8_v(color),
+                alpha: alpha_of_argb8(color) * state.opacity,
+                depth: z,
+            });
+            return;
+        }
+        // without an A-buffer fall back to plain alpha blending
+    }
+
+    write_color(state, &mut target.color[index], &mut target.depth[index], z, color);
+    if !target.hdr.is_empty() {
+        write_hdr(state, &mut target.hdr[index], color);
+    }
+    if state.blend == BlendMode::Opaque {
+        if let Some(g_buffer) = target.g_buffer.as_mut() { g_buffer.discard(index); }
+    }
+}
+
+// Multisampled version of `draw_triangle`.
+// Coverage and depth are evaluated at every sample of the pattern,
+// the color is computed once per pixel at the centroid of the covered samples.
+// Pixels in `occluded` tiles are skipped.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_triangle_msaa(
+    target: &mut Framebuffer,
+    state: &DrawState,
+    sc0: Vec2,
+    sc1: Vec2,
+    sc2: Vec2,
+    v0: Vertex,
+    v1: Vertex,
+    v2: Vertex,
+    rec0: f32,
+    rec1: f32,
+    rec2: f32,
+    texture: &Texture,
+    occluded: Option<&OccludedTiles>)
+{
+    let area = get_doubled_triangle_area(sc0, sc1, sc2);
+    if area == 0.0 { return; }
+    let reversed_global_area = 1.0 / area;
+
+    let samples = target.samples;
+    let pattern = antialiasing::sample_pattern(samples);
+
+    let min = sc0.min(sc1.min(sc2)).max(Vec2::ZERO);
+    let max = sc0.max(sc1.max(sc2)).min(target.size() - Vec2::ONE);
+    if min.x > max.x || min.y > max.y { return; }
+
+    let barycentric = |p: Vec2| glam::vec3(
+        get_doubled_triangle_area(p,   sc1, sc2) * reversed_global_area,
+        get_doubled_triangle_area(sc0, p,   sc2) * reversed_global_area,
+        get_doubled_triangle_area(sc0, sc1, p  ) * reversed_global_area,
+    );
+
+    for y in min.y as usize..=max.y as usize {
+        for x in min.x as usize..=max.x as usize {
+            if occluded.is_some_and(|o| o.contains(x, y)) { continue; }
+            let center = glam::vec2(x as f32 + 0.5, y as f32 + 0.5);
+            let index = coords_to_index(x, y, target.width);
 
-    buffer[index] = color;
+            let mut covered: u32 = 0;
+            let mut centroid = Vec2::ZERO;
+            let mut sample_z = [0.0; 8];
+
+            for (s, offset) in pattern.iter().enumerate() {
+                let w = barycentric(center + *offset);
+                if w.x < 0.0 || w.y < 0.0 || w.z < 0.0 { continue; }
+
+                let z = w.x * v0.pos.z + w.y * v1.pos.z + w.z * v2.pos.z;
+                let sample = index * samples + s;
+                if !state.depth_stencil_test(&mut target.sample_stencil[sample], z, target.sample_depth[sample]) { continue; }
+
+                covered |= 1 << s;
+                centroid += *offset;
+                sample_z[s] = z;
+            }
+
+            if covered == 0 { continue; }
+
+            if !state.color_write {
+                if state.depth.write {
+                    for (s, z) in sample_z.iter().enumerate().take(samples) {
+                        if covered & (1 << s) != 0 { target.sample_depth[index * samples + s] = *z; }
+                    }
+                }
+                continue;
+            }
+
+            let coverage = covered.count_ones();
+            let p = center + centroid / coverage as f32;
+
+            if let Some(debug_view) = target.debug_view.as_mut() {
+                let w = [p, p + Vec2::X, p + Vec2::Y].map(barycentric);
+                let color = debug_fragment(debug_view, index, w, v0, v1, v2, rec0, rec1, rec2, texture);
+                let state = DrawState { blend: BlendMode::Opaque, ..*state };
+                for (s, z) in sample_z.iter().enumerate().take(samples) {
+                    if covered & (1 << s) == 0 { continue; }
+                    let sample = index * samples + s;
+                    write_color(&state, &mut target.sample_color[sample], &mut target.sample_depth[sample], *z, color);
+                }
+                continue;
+            }
+
+            let w = barycentric(p);
+            let mut color = shade_fragment(w, v0, v1, v2, rec0, rec1, rec2, texture);
+
+            if state.lit && (target.lighting.is_some() || target.g_buffer.is_some()) {
+                let normal = interpolate_normal(w, v0, v1, v2, rec0, rec1, rec2);
+                let z = w.x * v0.pos.z + w.y * v1.pos.z + w.z * v2.pos.z;
+                let Some(lit) = light_fragment(target, state, index, z, color, normal) else {
+                    if state.depth.write {
+                        for (s, z) in sample_z.iter().enumerate().take(samples) {
+                            if covered & (1 << s) != 0 { target.sample_depth[index * samples + s] = *z; }
+                        }
+                    }
+                    continue;
+                };
+                color = lit;
+            }
+
+            if uses_a_buffer(state, target) {
+                if let Some(a_buffer) = target.a_buffer.as_mut() {
+                    a_buffer.append(index, Fragment {
+                        color: from_argb8_v(color),
+                        alpha: alpha_of_argb8(color) * state.opacity * coverage as f32 / samples as f32,
+                        depth: w.x * v0.pos.z + w.y * v1.pos.z + w.z * v2.pos.z,
+                    });
+                    continue;
+                }
+            }
+
+            for (s, z) in sample_z.iter().enumerate().take(samples) {
+                if covered & (1 << s) == 0 { continue; }
+                let sample = index * samples + s;
+                write_color(state, &mut target.sample_color[sample], &mut target.sample_depth[sample], *z, color);
+            }
+            if !target.hdr.is_empty() {
+                write_hdr(state, &mut target.hdr[index], color);
+            }
+            if state.blend == BlendMode::Opaque {
+                if let Some(g_buffer) = target.g_buffer.as_mut() { g_buffer.discard(index); }
+            }
+        }
+    }
 }
 
 pub fn line_from_points(pos0: glam::Vec2, pos1: glam::Vec2) -> (f32, f32, f32) {
@@ -101,28 +395,44 @@ pub fn line_from_points(pos0: glam::Vec2, pos1: glam::Vec2) -> (f32, f32, f32) {
 }
 
 pub fn raster_triangle(
+    v0: Vertex,
+    v1: Vertex,
+    v2: Vertex,
+    mvp: &Mat4,
+    texture: &Texture,
+    state: &DrawState,
+    target: &mut Framebuffer,
+) {
+    let clip = [v0, v1, v2].map(|v| *mvp * Vec4::from((v.pos, 1.0)));
+    raster_transformed_triangle(v0, v1, v2, clip, mvp, texture, state, target);
+}
+
+// Clips and draws a triangle whose vertices are already in clip space, in `clip`.
+// Vertices made by the near plane clipping are interpolated in clip space as well.
+#[allow(clippy::too_many_arguments)]
+pub fn raster_transformed_triangle(
     mut v0: Vertex,
     mut v1: Vertex,
     mut v2: Vertex,
+    clip: [Vec4; 3],
     mvp: &Mat4,
     texture: &Texture,
-    buffer: &mut Vec<u32>,
-    z_buffer: &mut Vec<f32>,
-    viewport_size: Vec2,
+    state: &DrawState,
+    target: &mut Framebuffer,
 ) {
+    let [clip0, clip1, clip2] = clip;
 
-    let clip0 = *mvp * Vec4::from((v0.pos, 1.0));
-    let clip1 = *mvp * Vec4::from((v1.pos, 1.0));
-    let clip2 = *mvp * Vec4::from((v2.pos, 1.0));    
+    let reverse_z = target.reverse_z;
+    let near = |clip: Vec4| near_plane_distance(clip, reverse_z);
 
     let mut num_of_vertices_behind:i8 = 0;
 
 
-    if clip0.z < 0.0 { num_of_vertices_behind += 1; }
-    if clip1.z < 0.0 { num_of_vertices_behind += 1; }
-    if clip2.z < 0.0 { num_of_vertices_behind += 1; }
+    if near(clip0) < 0.0 { num_of_vertices_behind += 1; }
+    if near(clip1) < 0.0 { num_of_vertices_behind += 1; }
+    if near(clip2) < 0.0 { num_of_vertices_behind += 1; }
 
-    if num_of_vertices_behind == 3 {println!("the triangle is outside of clip space"); return;}
+    if num_of_vertices_behind == 3 { return; }
 
     if num_of_vertices_behind == 0
     {
@@ -139,8 +449,8 @@ pub fn raster_triangle(
         v2.pos = clip2.xyz() * rec2;
 
         draw_triangle(
-            buffer,
-            z_buffer,
+            target,
+            state,
             v0,
             v1,
             v2,
@@ -148,9 +458,7 @@ pub fn raster_triangle(
             rec1,
             rec2,
             texture,
-            viewport_size,
             mvp);
-        return;
     }
     else if num_of_vertices_behind == 1
     {
@@ -162,19 +470,19 @@ pub fn raster_triangle(
         let     clip_of_stay1: Vec4;
 
         // Checking which vertex to slice
-        if clip0.z < 0.0       { vertex_to_slice = v0; clip_of_slice = clip0; vertex_to_stay0 = v1; clip_of_stay0 = clip1; vertex_to_stay1 = v2; clip_of_stay1 = clip2; }
-        else if clip1.z < 0.0  { vertex_to_slice = v1; clip_of_slice = clip1; vertex_to_stay0 = v0; clip_of_stay0 = clip0; vertex_to_stay1 = v2; clip_of_stay1 = clip2; }
+        if near(clip0) < 0.0       { vertex_to_slice = v0; clip_of_slice = clip0; vertex_to_stay0 = v1; clip_of_stay0 = clip1; vertex_to_stay1 = v2; clip_of_stay1 = clip2; }
+        else if near(clip1) < 0.0  { vertex_to_slice = v1; clip_of_slice = clip1; vertex_to_stay0 = v0; clip_of_stay0 = clip0; vertex_to_stay1 = v2; clip_of_stay1 = clip2; }
         else                   { vertex_to_slice = v2; clip_of_slice = clip2; vertex_to_stay0 = v1; clip_of_stay0 = clip1; vertex_to_stay1 = v0; clip_of_stay1 = clip0; }
 
         // Calculating new vertices
-        let coef0 = clip_of_stay0.z / (clip_of_stay0.z - clip_of_slice.z);
-        let coef1 = clip_of_stay1.z / (clip_of_stay1.z - clip_of_slice.z);
+        let coef0 = near(clip_of_stay0) / (near(clip_of_stay0) - near(clip_of_slice));
+        let coef1 = near(clip_of_stay1) / (near(clip_of_stay1) - near(clip_of_slice));
 
         let mut new_vertex0 = vertex_to_stay0 + (vertex_to_slice - vertex_to_stay0) * coef0;
         let mut new_vertex1 = vertex_to_stay1 + (vertex_to_slice - vertex_to_stay1) * coef1;
 
-        let new_clip0 = *mvp * Vec4::from((new_vertex0.pos, 1.0));
-        let new_clip1 = *mvp * Vec4::from((new_vertex1.pos, 1.0));
+        let new_clip0 = clip_of_stay0 + (clip_of_slice - clip_of_stay0) * coef0;
+        let new_clip1 = clip_of_stay1 + (clip_of_slice - clip_of_stay1) * coef1;
 
 
         // Deviding by homogenyous coordinates
@@ -196,8 +504,8 @@ pub fn raster_triangle(
 
 
         draw_triangle(
-            buffer,
-            z_buffer,
+            target,
+            state,
             new_vertex0,
             vertex_to_stay0,
             new_vertex1,
@@ -205,12 +513,11 @@ pub fn raster_triangle(
             rec_stay0,
             rec_new1,
             texture,
-            viewport_size,
             mvp);
 
         draw_triangle(
-            buffer,
-            z_buffer,
+            target,
+            state,
             vertex_to_stay1,
             vertex_to_stay0,
             new_vertex1,
@@ -218,7 +525,6 @@ pub fn raster_triangle(
             rec_stay0,
             rec_new1,
             texture,
-            viewport_size,
             mvp);
     }
     else
@@ -231,19 +537,19 @@ pub fn raster_triangle(
         let     clip_of_slice1: Vec4;
 
         // Checking which vertex is inside our clip space
-        if clip0.z > 0.0       { vertex_to_stay = v0; clip_of_stay = clip0; vertex_to_slice0 = v1; clip_of_slice0 = clip1; vertex_to_slice1 = v2; clip_of_slice1 = clip2; }
-        else if clip1.z > 0.0  { vertex_to_stay = v1; clip_of_stay = clip1; vertex_to_slice0 = v0; clip_of_slice0 = clip0; vertex_to_slice1 = v2; clip_of_slice1 = clip2; }
+        if near(clip0) > 0.0       { vertex_to_stay = v0; clip_of_stay = clip0; vertex_to_slice0 = v1; clip_of_slice0 = clip1; vertex_to_slice1 = v2; clip_of_slice1 = clip2; }
+        else if near(clip1) > 0.0  { vertex_to_stay = v1; clip_of_stay = clip1; vertex_to_slice0 = v0; clip_of_slice0 = clip0; vertex_to_slice1 = v2; clip_of_slice1 = clip2; }
         else                   { vertex_to_stay = v2; clip_of_stay = clip2; vertex_to_slice0 = v1; clip_of_slice0 = clip1; vertex_to_slice1 = v0; clip_of_slice1 = clip0; }
 
         // Calculating new vertices
-        let coef0 = clip_of_stay.z / (clip_of_stay.z - clip_of_slice0.z);
-        let coef1 = clip_of_stay.z / (clip_of_stay.z - clip_of_slice1.z);
+        let coef0 = near(clip_of_stay) / (near(clip_of_stay) - near(clip_of_slice0));
+        let coef1 = near(clip_of_stay) / (near(clip_of_stay) - near(clip_of_slice1));
 
         let mut new_vertex0 = vertex_to_stay + (vertex_to_slice0 - vertex_to_stay) * coef0;
         let mut new_vertex1 = vertex_to_stay + (vertex_to_slice1 - vertex_to_stay) * coef1;
 
-        let new_clip0 = *mvp * Vec4::from((new_vertex0.pos, 1.0));
-        let new_clip1 = *mvp * Vec4::from((new_vertex1.pos, 1.0));
+        let new_clip0 = clip_of_stay + (clip_of_slice0 - clip_of_stay) * coef0;
+        let new_clip1 = clip_of_stay + (clip_of_slice1 - clip_of_stay) * coef1;
 
 
         // Deviding by homogenyous coordinates
@@ -261,8 +567,8 @@ pub fn raster_triangle(
 
 
         draw_triangle(
-            buffer,
-            z_buffer,
+            target,
+            state,
             new_vertex0,
             vertex_to_stay,
             new_vertex1,
@@ -270,48 +576,180 @@ pub fn raster_triangle(
             rec_stay0,
             rec_new1,
             texture,
-            viewport_size,
             mvp);
     }
 }
 
 pub fn raster_mesh(
     mesh: &Mesh,
-    _model: &Mat4,
+    model: &Mat4,
     mvp: &Mat4,
     texture: &Texture,
-    buffer: &mut Vec<u32>,
-    z_buffer: &mut Vec<f32>,
-    viewport_size: Vec2,
+    state: &DrawState,
+    target: &mut Framebuffer,
 ) {
-    for triangle in mesh.triangles() {
-        let vertices = mesh.get_vertices_from_triangle(*triangle);
-        raster_triangle(
-            *vertices[0],
-            *vertices[1],
-            *vertices[2],
-            mvp,
-            texture,
-            buffer,
-            z_buffer,
-            viewport_size,
-        );
+    // the planes of `mvp` are in model space, where the bounds of the mesh are
+    if !Frustum::from_matrix(mvp).intersects_aabb(mesh.aabb()) {
+        return;
+    }
+    raster_mesh_tinted(mesh, model, mvp, Vec3::ONE, texture, state, target);
+}
+
+// Draws `mesh` once per transform, placed by `model * transform`. The frustum is extracted
+// once for all of them and each copy outside of it is skipped from its bounds alone,
+// as is each copy an occlusion query finds no visible sample of.
+// `colors` tint the vertex colors of each copy.
+#[allow(clippy::too_many_arguments)]
+pub fn raster_mesh_instanced(
+    mesh: &Mesh,
+    model: &Mat4,
+    transforms: &[Transform],
+    colors: Option<&[Vec3]>,
+    view_projection: &Mat4,
+    texture: &Texture,
+    state: &DrawState,
+    target: &mut Framebuffer,
+) {
+    let frustum = Frustum::from_matrix(view_projection);
+    // with a hierarchical depth, copies hidden behind what is already drawn are skipped as well,
+    // the stencil needs every fragment
+    let query = target.hiz.is_some() && !state.stencil.enabled;
+    for (i, transform) in transforms.iter().enumerate() {
+        let instance_model = *model * transform.local();
+        if !frustum.intersects_aabb(&mesh.aabb().transform(&instance_model)) {
+            continue;
+        }
+        if query && hiz::occlusion_query(target, mesh.aabb(), &(*view_projection * instance_model), state) == 0 {
+            continue;
+        }
+        let color = colors.and_then(|colors| colors.get(i)).copied().unwrap_or(Vec3::ONE);
+        raster_mesh_tinted(mesh, &instance_model, &(*view_projection * instance_model), color, texture, state, target);
+    }
+}
+
+// the vertex colors are multiplied with `tint`, `model` places the normals in world space
+fn raster_mesh_tinted(
+    mesh: &Mesh,
+    model: &Mat4,
+    mvp: &Mat4,
+    tint: Vec3,
+    texture: &Texture,
+    state: &DrawState,
+    target: &mut Framebuffer,
+) {
+    let tinted = |vertex: &Vertex| Vertex { c: vertex.c * tint, ..*vertex };
+    let transformed = PostTransform::new(mesh.vertices(), model, mvp, target.reverse_z);
+    for (index, triangle) in mesh.triangles().iter().enumerate() {
+        if index % hiz::UPDATE_INTERVAL == 0 {
+            target.update_hiz();
+        }
+        if let Some(debug_view) = target.debug_view.as_mut() {
+            debug_view.triangle = index;
+        }
+        let vertices = triangle.to_array().map(|i| Vertex { normal: transformed.normals[i as usize], ..tinted(&mesh.vertices()[i as usize]) });
+        match state.polygon_mode {
+            PolygonMode::Fill if transformed.is_outside(*triangle) => {}
+            PolygonMode::Fill if transformed.needs_clipping(*triangle) => raster_transformed_triangle(
+                vertices[0],
+                vertices[1],
+                vertices[2],
+                transformed.clip(*triangle),
+                mvp,
+                texture,
+                state,
+                target,
+            ),
+            // the common case, all three vertices come straight from the vertex stage
+            PolygonMode::Fill => {
+                let [i0, i1, i2] = triangle.to_array().map(|i| i as usize);
+                draw_triangle(
+                    target,
+                    state,
+                    tinted(&transformed.projected[i0]),
+                    tinted(&transformed.projected[i1]),
+                    tinted(&transformed.projected[i2]),
+                    transformed.rec[i0],
+                    transformed.rec[i1],
+                    transformed.rec[i2],
+                    texture,
+                    mvp,
+                );
+            }
+            PolygonMode::Line => {
+                for i in 0..3 {
+                    raster_line(&vertices[i], &vertices[(i + 1) % 3], mvp, texture, state, target);
+                }
+            }
+            PolygonMode::Point => {
+                for vertex in &vertices {
+                    raster_point(vertex, mvp, texture, state, target);
+                }
+            }
+        }
     }
+
+    let vertices = mesh.vertices();
+    for line in mesh.lines() {
+        let (start, end) = (tinted(&vertices[line.x as usize]), tinted(&vertices[line.y as usize]));
+        raster_line(&start, &end, mvp, texture, state, target);
+    }
+    for point in mesh.points() {
+        raster_point(&tinted(&vertices[*point as usize]), mvp, texture, state, target);
+    }
+}
+
+// Draws the mesh marking its pixels in the stencil buffer, then a scaled up copy
+// with `outline_texture` only where the mask is not set, which leaves a silhouette outline.
+#[allow(clippy::too_many_arguments)]
+pub fn raster_mesh_outlined(
+    mesh: &Mesh,
+    model: &Mat4,
+    view_projection: &Mat4,
+    texture: &Texture,
+    outline_texture: &Texture,
+    outline_scale: f32,
+    state: &DrawState,
+    target: &mut Framebuffer,
+) {
+    const OUTLINE_STENCIL: u8 = 1;
+
+    let mask_state = DrawState {
+        stencil: StencilState::write(OUTLINE_STENCIL),
+        ..*state
+    };
+    raster_mesh(mesh, model, &(*view_projection * *model), texture, &mask_state, target);
+
+    let outline_state = DrawState {
+        stencil: StencilState::not_equal(OUTLINE_STENCIL),
+        depth: DepthState {
+            compare: CompareFunc::Always,
+            write: false,
+            ..state.depth
+        },
+        lit: false,
+        ..DrawState::default()
+    };
+    let outline_model = *model * Mat4::from_scale(glam::Vec3::splat(outline_scale));
+    raster_mesh(mesh, &outline_model, &(*view_projection * outline_model), outline_texture, &outline_state, target);
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn draw_triangle(
-    buffer: &mut Vec<u32>,
-    z_buffer: &mut Vec<f32>,
-    clipped_v0: Vertex,
-    clipped_v1: Vertex,
-    clipped_v2: Vertex,
+    target: &mut Framebuffer,
+    state: &DrawState,
+    mut clipped_v0: Vertex,
+    mut clipped_v1: Vertex,
+    mut clipped_v2: Vertex,
     rec0: f32,
     rec1: f32,
     rec2: f32,
     texture: &Texture,
-    viewport_size: glam::Vec2,
     _mvp: &glam::Mat4, )
 {
+    let viewport_size = target.size();
+    let (width, height) = (target.width, target.height);
+    let height_f = height as f32;
+
     // screeen coordinates remapped to window
     let sc0 = glam::vec2(
         map_to_range(clipped_v0.pos.x, -1.0, 1.0, 0.0, viewport_size.x),
@@ -325,6 +763,51 @@ pub fn draw_triangle(
         map_to_range(clipped_v2.pos.x, -1.0, 1.0, 0.0, viewport_size.x),
         map_to_range(-clipped_v2.pos.y, -1.0, 1.0, 0.0, viewport_size.y),
     );
+
+    // depth as a plane over the screen
+    let area = get_doubled_triangle_area(sc0, sc1, sc2);
+    let (dzdx, dzdy) = if area != 0.0 {
+        let dz1 = clipped_v1.pos.z - clipped_v0.pos.z;
+        let dz2 = clipped_v2.pos.z - clipped_v0.pos.z;
+        (
+            (dz1 * (sc2.y - sc0.y) - dz2 * (sc1.y - sc0.y)) / area,
+            (dz2 * (sc1.x - sc0.x) - dz1 * (sc2.x - sc0.x)) / area,
+        )
+    } else {
+        (0.0, 0.0)
+    };
+
+    // polygon offset, the steepest slope comes from the plane equation of the depth
+    if (state.depth.bias != 0.0 || state.depth.slope_scaled_bias != 0.0) && area != 0.0 {
+        let offset = state.depth.offset(dzdx.abs().max(dzdy.abs()));
+        clipped_v0.pos.z += offset;
+        clipped_v1.pos.z += offset;
+        clipped_v2.pos.z += offset;
+    }
+
+    // Hierarchical depth, the whole triangle or the tiles it is behind everything in are skipped.
+    // The rectangle is a pixel larger as the scanlines sample at pixel corners, and the depth
+    // range is the one of the plane over it, pixels just off the edges extrapolate the depth.
+    let bounds_min = sc0.min(sc1.min(sc2)) - Vec2::ONE;
+    let bounds_max = sc0.max(sc1.max(sc2)) + Vec2::ONE;
+    let corner_z = |corner: Vec2| clipped_v0.pos.z + dzdx * (corner.x - sc0.x) + dzdy * (corner.y - sc0.y);
+    let corners = [bounds_min, bounds_max, glam::vec2(bounds_min.x, bounds_max.y), glam::vec2(bounds_max.x, bounds_min.y)].map(corner_z);
+    let depth_range = (corners.into_iter().fold(f32::INFINITY, f32::min), corners.into_iter().fold(f32::NEG_INFINITY, f32::max));
+    let occluded = target.occluded_tiles(state, bounds_min, bounds_max, depth_range);
+    if occluded.as_ref().is_some_and(|o| o.all()) {
+        return;
+    }
+    if state.depth.write {
+        target.mark_depth_written(bounds_min, bounds_max, depth_range);
+    }
+    // the scanlines can run past the corners of thin triangles, they are kept to the rectangle
+    let (first_row, last_row) = (bounds_min.y.max(0.0) as usize, bounds_max.y.max(0.0) as usize);
+    let hidden = |x: usize, y: usize| y < first_row || y > last_row || occluded.as_ref().is_some_and(|o| o.contains(x, y));
+
+    if target.samples > 1 {
+        draw_triangle_msaa(target, state, sc0, sc1, sc2, clipped_v0, clipped_v1, clipped_v2, rec0, rec1, rec2, texture, occluded.as_ref());
+        return;
+    }
     
     let dx_0 = (sc0.x - sc1.x).abs();
     let dx_1 = (sc2.x - sc1.x).abs();
@@ -363,21 +846,21 @@ pub fn draw_triangle(
         return;
     }
 
-    let min_x = (sc0.x.min(sc1.x.min(sc2.x)) as usize).max(0);
-    let max_x = (sc0.x.max(sc1.x.max(sc2.x)) as usize).min(WIDTH - 1) + 1;
+    let min_x = sc0.x.min(sc1.x.min(sc2.x)) as usize;
+    let max_x = (sc0.x.max(sc1.x.max(sc2.x)) as usize).min(width - 1) + 1;
 
 
     let reversed_global_area = 1.0 / get_doubled_triangle_area(sc0, sc1, sc2);
 
 
     if  sc0.y < 0.0            || sc1.y < 0.0            || sc2.y < 0.0            ||
-        sc0.y > HEIGHT_F - 1.0 || sc1.y > HEIGHT_F - 1.0 || sc2.y > HEIGHT_F - 1.0
+        sc0.y > height_f - 1.0 || sc1.y > height_f - 1.0 || sc2.y > height_f - 1.0
     {
         if (a*pivot_point.x + b*pivot_point.y - c).signum() != b.signum() // pivot point below the line
         {
             for x_usize in min_x..max_x
             {
-                if x_usize >= WIDTH { continue; }
+                if x_usize >= width { continue; }
 
                 let x_f32 = x_usize as f32 - 0.5;
                 let mut y_f32 = find_maximal_y(pivot_line0, pivot_line1, x_f32).max(0.0);
@@ -388,10 +871,11 @@ pub fn draw_triangle(
                 let mut y_usize = y_f32 as usize;
 
                 let temporal_value = a*x_f32 - c;
-                while (b*y_f32 + temporal_value).signum() != b.signum() && (b*y_f32 + temporal_value != 0.0) && y_usize < HEIGHT{
-                    let index = coords_to_index(x_usize, y_usize, WIDTH);
-                    
-                    draw_pixel(buffer, z_buffer, index, x_f32, y_f32, sc0, sc1, sc2, clipped_v0, clipped_v1, clipped_v2, rec0, rec1, rec2, reversed_global_area, texture);
+                while (b*y_f32 + temporal_value).signum() != b.signum() && (b*y_f32 + temporal_value != 0.0) && y_usize < height{
+                    if !hidden(x_usize, y_usize) {
+                        let index = coords_to_index(x_usize, y_usize, width);
+                        draw_pixel(target, state, index, x_f32, y_f32, sc0, sc1, sc2, clipped_v0, clipped_v1, clipped_v2, rec0, rec1, rec2, reversed_global_area, texture);
+                    }
                     y_f32 += 1.0;
                     y_usize += 1;
                 }
@@ -401,10 +885,10 @@ pub fn draw_triangle(
         {
             for x_usize in min_x..max_x
             {
-                if x_usize >= WIDTH { continue; }
+                if x_usize >= width { continue; }
 
                 let x_f32 = x_usize as f32 - 0.5;
-                let mut y_f32 = find_minimal_y(pivot_line0, pivot_line1, x_f32).min(HEIGHT_F - 1.0);
+                let mut y_f32 = find_minimal_y(pivot_line0, pivot_line1, x_f32).min(height_f - 1.0);
 
 
                 let mut y_usize = y_f32 as usize;
@@ -415,9 +899,10 @@ pub fn draw_triangle(
                 let temporal_value = a*x_f32 - c;
 
                 while (b*y_f32 + temporal_value).signum() == b.signum() && (b*y_f32 + temporal_value != 0.0) && y_usize > 0{
-                    let index = coords_to_index(x_usize, y_usize, WIDTH);
-                    
-                    draw_pixel(buffer, z_buffer, index, x_f32, y_f32, sc0, sc1, sc2, clipped_v0, clipped_v1, clipped_v2, rec0, rec1, rec2, reversed_global_area, texture);
+                    if !hidden(x_usize, y_usize) {
+                        let index = coords_to_index(x_usize, y_usize, width);
+                        draw_pixel(target, state, index, x_f32, y_f32, sc0, sc1, sc2, clipped_v0, clipped_v1, clipped_v2, rec0, rec1, rec2, reversed_global_area, texture);
+                    }
                     y_f32 -= 1.0;
                     y_usize -= 1;
                 }
@@ -430,10 +915,10 @@ pub fn draw_triangle(
         {
             for x_usize in min_x..max_x
             {
-                if x_usize >= WIDTH { continue; }
+                if x_usize >= width { continue; }
 
                 let x_f32 = x_usize as f32 - 0.5;
-                let mut y_f32 = find_maximal_y(pivot_line0, pivot_line1, x_f32);
+                let mut y_f32 = find_maximal_y(pivot_line0, pivot_line1, x_f32).max(0.0);
 
                 
                 y_f32 -= 0.500001;
@@ -442,10 +927,11 @@ pub fn draw_triangle(
                 let mut y_usize = y_f32 as usize;
 
                 let temporal_value = a*x_f32 - c;
-                while (b*y_f32 + temporal_value).signum() != b.signum() && (b*y_f32 + temporal_value != 0.0) && y_usize < HEIGHT{
-                    let index = coords_to_index(x_usize, y_usize, WIDTH);
-                    
-                    draw_pixel(buffer, z_buffer, index, x_f32, y_f32, sc0, sc1, sc2, clipped_v0, clipped_v1, clipped_v2, rec0, rec1, rec2, reversed_global_area, texture);
+                while (b*y_f32 + temporal_value).signum() != b.signum() && (b*y_f32 + temporal_value != 0.0) && y_usize < height{
+                    if !hidden(x_usize, y_usize) {
+                        let index = coords_to_index(x_usize, y_usize, width);
+                        draw_pixel(target, state, index, x_f32, y_f32, sc0, sc1, sc2, clipped_v0, clipped_v1, clipped_v2, rec0, rec1, rec2, reversed_global_area, texture);
+                    }
                     y_f32 += 1.0;
                     y_usize += 1;
                 }
@@ -455,10 +941,10 @@ pub fn draw_triangle(
         {
             for x_usize in min_x..max_x
             {
-                if x_usize >= WIDTH { continue; }
+                if x_usize >= width { continue; }
 
                 let x_f32 = x_usize as f32 - 0.5;
-                let mut y_f32 = find_minimal_y(pivot_line0, pivot_line1, x_f32);
+                let mut y_f32 = find_minimal_y(pivot_line0, pivot_line1, x_f32).min(height_f - 1.0);
 
                 let mut y_usize = y_f32 as usize;
 
@@ -469,9 +955,10 @@ pub fn draw_triangle(
                 let temporal_value = a*x_f32 - c;
 
                 while (b*y_f32 + temporal_value).signum() == b.signum() && (b*y_f32 + temporal_value != 0.0) && y_usize > 0{
-                    let index = coords_to_index(x_usize, y_usize, WIDTH);
-                    
-                    draw_pixel(buffer, z_buffer, index, x_f32, y_f32, sc0, sc1, sc2, clipped_v0, clipped_v1, clipped_v2, rec0, rec1, rec2, reversed_global_area, texture);
+                    if !hidden(x_usize, y_usize) {
+                        let index = coords_to_index(x_usize, y_usize, width);
+                        draw_pixel(target, state, index, x_f32, y_f32, sc0, sc1, sc2, clipped_v0, clipped_v1, clipped_v2, rec0, rec1, rec2, reversed_global_area, texture);
+                    }
                     y_f32 -= 1.0;
                     y_usize -= 1;
                 }
@@ -516,9 +1003,43 @@ pub fn find_maximal_y(line0: (f32, f32, f32), line1: (f32, f32, f32), x: f32) ->
     }
 }
 
+pub fn create_render_target(anti_aliasing: AntiAliasing) -> Framebuffer {
+    let scale = anti_aliasing.scale();
+    let mut target = Framebuffer::new(WIDTH * scale, HEIGHT * scale);
+    target.enable_a_buffer(A_BUFFER_BUDGET);
+    target.set_samples(anti_aliasing.samples());
+    target.enable_hdr();
+    target.enable_hiz(hiz::DEFAULT_TILE_SIZE);
+    target
+}
+
+// The depth effects come first, while the depth buffer still matches the colors, then the
+// effects working on the image alone, anti-aliasing before the ones that blur or add noise.
+pub fn create_post_stack() -> PostStack {
+    let mut post_stack = PostStack::new();
+    post_stack.push(depth_effects::Ssao::default());
+    post_stack.push(depth_effects::Fog::default());
+    post_stack.push(depth_effects::DepthOfField::default());
+    post_stack.push(post_processing::Bloom::default());
+    match post_processing::Lut3d::load_cube(Path::new(LUT_PATH)) {
+        Ok(lut) => { post_stack.push(post_processing::ColorGrading::new(lut)); }
+        Err(e) => println!("Failed to load {}: {}", LUT_PATH, e),
+    }
+    post_stack.push(post_processing::Fxaa::default());
+    post_stack.push(post_processing::Sharpen::default());
+    post_stack.push(post_processing::ChromaticAberration::default());
+    post_stack.push(post_processing::Vignette::default());
+    post_stack
+}
+
 fn main() {
-    let mut buffer: Vec<u32> = vec![0; WIDTH * HEIGHT];
-    let mut z_buffer: Vec<f32> = vec![1.0; WIDTH * HEIGHT];
+    let mut anti_aliasing = AntiAliasing::Off;
+    let mut target = create_render_target(anti_aliasing);
+    // supersampled frames are downsampled into it
+    let mut output = Framebuffer::new(WIDTH, HEIGHT);
+    output.enable_hdr();
+
+    let mut post_stack = create_post_stack();
 
     let mut window = Window::new(
         "Test - ESC to exit",
@@ -540,13 +1061,76 @@ fn main() {
         ..Default::default()
     };
 
-    let mesh = load_gltf(Path::new(MESH_PATH));
+    let (mut lod, materials, warnings) = lod::load_lod_mesh(Path::new(MESH_PATH), LOD_LEVELS).unwrap_or_else(|e| {
+        panic!("Mesh failed to load.\nCaused error: {}", e);
+    });
+    for warning in &warnings {
+        println!("Warning: {}", warning);
+    }
+    for (index, level) in lod.levels().iter().enumerate() {
+        println!("LOD {}: {} triangles, {} asked for", index, level.mesh.triangles().len(), level.target_triangles);
+    }
+
+    let mut rig = animation::load_rig(Path::new(MESH_PATH)).unwrap_or_else(|e| {
+        println!("Animations failed to load: {}", e);
+        None
+    });
+    if let Some(rig) = &rig {
+        let names: Vec<&str> = rig.animations.iter().map(|a| a.name.as_str()).collect();
+        println!("Skinned: {}, animations: {:?}", rig.skin.is_some(), names);
+    }
+    let mut animation = 0;
+    let mut playback = Playback::default();
+    let mut skinning_method = SkinningMethod::LinearBlend;
+
+    // copies of the mesh from `EXT_mesh_gpu_instancing`, or a grid shown with I
+    let file_instances = instancing::load_instances(Path::new(MESH_PATH)).unwrap_or_else(|e| {
+        println!("Instances failed to load: {}", e);
+        Instances::default()
+    });
+    if !file_instances.is_empty() {
+        println!("Instances: {}", file_instances.transforms.len());
+    }
+    let mut instances = file_instances.clone();
 
     let transform_of_go = Transform::from_rotation(glam::Quat::from_euler(glam::EulerRot::XYZ, 0.0, 0.0, 0.0));
 
-    let texture = Texture::load(Path::new(TEXT_PATH));
+    // the diffuse map of the first material replaces the default texture,
+    // scans and prints are shown with their vertex colors only
+    let texture = materials
+        .iter()
+        .find_map(Material::diffuse_texture)
+        .unwrap_or_else(|| {
+            if utils::is_untextured_format(Path::new(MESH_PATH)) {
+                Texture::from_color(utils::to_argb8(255, 255, 255, 255))
+            } else {
+                Texture::load(Path::new(TEXT_PATH))
+            }
+        });
+
+    let mut state = DrawState {
+        surface: materials.first().map(Surface::from_material).unwrap_or_default(),
+        ..Default::default()
+    };
+    let mut shading = Shading::Unlit;
+    let mut debug_mode: Option<DebugMode> = None;
+
+    let mut outline = false;
+    let outline_texture = Texture::from_color(utils::to_argb8(255, 255, 160, 0));
+
+    let mut wireframe = false;
+    let wireframe_texture = Texture::from_color(utils::to_argb8(255, 40, 220, 80));
 
-    let window_size = glam::vec2(WIDTH as f32, HEIGHT as f32);
+    // built for a level of detail the first time something is picked on it
+    let mut bvhs: Vec<Option<Bvh>> = vec![None; lod.levels().len()];
+    // level of detail, instance and triangle under the cursor at the last click
+    let mut picked: Option<(usize, usize, Hit)> = None;
+    let mut mouse_was_down = false;
+    let highlight_texture = Texture::from_color(utils::to_argb8(255, 255, 60, 200));
+
+    // hierarchical depth culling, and a depth only pass before the shaded one
+    let mut hiz_enabled = true;
+    let mut depth_prepass = false;
 
     let mut mouse_pos = (WIDTH_F / 2.0, HEIGHT_F / 2.0);
     
@@ -554,6 +1138,7 @@ fn main() {
     window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
 
     let mut now = Instant::now();
+    let start = now;
     
     while window.is_open() && !window.is_key_down(Key::Escape) {
 
@@ -561,20 +1146,403 @@ fn main() {
         now = Instant::now();
 
         handle_camera(&mut camera, &window, &mut mouse_pos, dt);
-        clear_buffer(&mut buffer);
-        clear_z_buffer(&mut z_buffer);
-
-        raster_mesh(
-            &mesh,
-            &transform_of_go.local(),
-            &(camera.projection() * camera.view() * transform_of_go.local()),
-            &texture,
-            &mut buffer,
-            &mut z_buffer,
-            window_size,
+        handle_draw_state(&mut state, &window);
+        if handle_anti_aliasing(&mut anti_aliasing, &window) {
+            target = create_render_target(anti_aliasing);
+        }
+        handle_post_stack(&mut post_stack, &window);
+        handle_reverse_z(&mut camera, &mut state, &mut target, &window);
+        handle_hiz(&mut hiz_enabled, &mut depth_prepass, &mut target, &window);
+        handle_shading(&mut shading, &mut target, &camera, start.elapsed().as_secs_f32(), &window);
+        handle_debug_view(&mut debug_mode, &mut target, &camera, &window);
+        output.set_reverse_z(camera.reverse_z);
+        target.clear();
+
+        if window.is_key_pressed(Key::O, KeyRepeat::No) {
+            outline = !outline;
+        }
+
+        if window.is_key_pressed(Key::I, KeyRepeat::No) && file_instances.is_empty() {
+            instances = if instances.is_empty() { Instances::grid(3, 3, 2.5) } else { Instances::default() };
+            println!("Instances: {}", instances.transforms.len());
+        }
+
+        // the mesh node follows its animation, skinned meshes are placed by their joints
+        let mut model = transform_of_go.local();
+        if let Some(rig) = &mut rig {
+            handle_animation(rig, &mut animation, &mut playback, &mut skinning_method, &window, dt);
+            rig.pose(animation, playback.time);
+            if rig.skin.is_none() {
+                model = model * rig.nodes.global(rig.node) * rig.nodes.rest_global(rig.node).inverse();
+            }
+        }
+
+        let screen_size = lod.screen_size(&model, &camera.view(), &camera.projection());
+        if lod.select(screen_size) {
+            match (lod.current(), lod.mesh()) {
+                (Some(level), Some(mesh)) => println!("LOD: {} ({} triangles)", level, mesh.triangles().len()),
+                _ => println!("LOD: culled"),
+            }
+        }
+        let (Some(level), Some(mesh)) = (lod.current(), lod.mesh()) else {
+            present_frame(&mut target, &mut output, anti_aliasing, &mut post_stack, &camera, &mut window);
+            continue;
+        };
+        // the vertex stage blends the morph targets and applies the skin
+        let deformed = (mesh.is_skinned() || !mesh.morph_targets().is_empty()).then(|| {
+            let morph_weights = rig.as_ref().and_then(Rig::morph_weights);
+            let joint_matrices = rig.as_ref().and_then(|rig| Some(rig.skin.as_ref()?.joint_matrices(&rig.nodes)));
+            mesh.deform(morph_weights, joint_matrices.as_deref(), skinning_method)
+        });
+        let mesh = deformed.as_ref().unwrap_or(mesh);
+
+        // model matrix of every drawn copy of the mesh
+        let instance_models: Vec<Mat4> = if instances.is_empty() {
+            vec![model]
+        } else {
+            instances.transforms.iter().map(|transform| model * transform.local()).collect()
+        };
+
+        let mouse_down = window.get_mouse_down(MouseButton::Left);
+        if mouse_down && !mouse_was_down {
+            // the hierarchy of a deformed mesh is only good for the current pose
+            let hit = if deformed.is_some() {
+                pick(mesh, &Bvh::build(mesh), &camera, &instance_models, mouse_pos)
+            } else {
+                let bvh = bvhs[level].get_or_insert_with(|| Bvh::build(mesh));
+                pick(mesh, bvh, &camera, &instance_models, mouse_pos)
+            };
+            picked = hit.map(|(instance, hit)| (level, instance, hit));
+        }
+        mouse_was_down = mouse_down;
+        // a pick is only valid on the level it was made on, and while its instance is drawn
+        let picked_triangle = picked
+            .filter(|(picked_level, instance, _)| *picked_level == level && *instance < instance_models.len())
+            .map(|(_, instance, hit)| (instance_models[instance], hit.triangle));
+
+        let draw_mesh = |state: &DrawState, target: &mut Framebuffer| {
+            if !instances.is_empty() {
+                raster_mesh_instanced(
+                    mesh,
+                    &model,
+                    &instances.transforms,
+                    instances.colors(),
+                    &(camera.projection() * camera.view()),
+                    &texture,
+                    state,
+                    target,
+                );
+            } else {
+                raster_mesh(
+                    mesh,
+                    &model,
+                    &(camera.projection() * camera.view() * model),
+                    &texture,
+                    state,
+                    target,
+                );
+            }
+        };
+
+        if instances.is_empty() && (outline || picked_triangle.is_some()) {
+            raster_mesh_outlined(
+                mesh,
+                &model,
+                &(camera.projection() * camera.view()),
+                &texture,
+                &outline_texture,
+                OUTLINE_SCALE,
+                &state,
+                &mut target,
+            );
+        } else if depth_prepass && state.depth.write {
+            // the depth is laid down first, the equal depth then passes only for the nearest fragments
+            draw_mesh(&DrawState { color_write: false, ..state }, &mut target);
+            draw_mesh(&DrawState { depth: DepthState { write: false, ..state.depth }, ..state }, &mut target);
+        } else {
+            draw_mesh(&state, &mut target);
+        }
+
+        if window.is_key_pressed(Key::F, KeyRepeat::No) {
+            wireframe = !wireframe;
+        }
+
+        if wireframe && !instances.is_empty() {
+            raster_mesh_instanced(
+                mesh,
+                &model,
+                &instances.transforms,
+                None,
+                &(camera.projection() * camera.view()),
+                &wireframe_texture,
+                &wireframe_state(&state, camera.reverse_z),
+                &mut target,
+            );
+        } else if wireframe {
+            raster_mesh(
+                mesh,
+                &model,
+                &(camera.projection() * camera.view() * model),
+                &wireframe_texture,
+                &wireframe_state(&state, camera.reverse_z),
+                &mut target,
+            );
+        }
+
+        if let Some((picked_model, triangle)) = picked_triangle {
+            let highlight = Mesh::from_vertices(
+                &[glam::UVec3::new(0, 1, 2)],
+                &mesh.get_vertices_from_triangle(mesh.triangles()[triangle]).map(|v| *v),
+            );
+            raster_mesh(
+                &highlight,
+                &picked_model,
+                &(camera.projection() * camera.view() * picked_model),
+                &highlight_texture,
+                &DrawState { polygon_mode: PolygonMode::Fill, ..wireframe_state(&state, camera.reverse_z) },
+                &mut target,
+            );
+        }
+
+        present_frame(&mut target, &mut output, anti_aliasing, &mut post_stack, &camera, &mut window);
+    }
+}
+
+// P plays and pauses, the arrows scrub, N goes to the next animation and K switches the skinning method
+fn handle_animation(
+    rig: &Rig,
+    animation: &mut usize,
+    playback: &mut Playback,
+    method: &mut SkinningMethod,
+    window: &Window,
+    dt: f32,
+) {
+    if window.is_key_pressed(Key::N, KeyRepeat::No) && !rig.animations.is_empty() {
+        *animation = (*animation + 1) % rig.animations.len();
+        playback.time = 0.0;
+        println!("Animation: {} ({})", animation, rig.animations[*animation].name);
+    }
+    let duration = rig.animations.get(*animation).map_or(0.0, |a| a.duration);
+    if window.is_key_pressed(Key::P, KeyRepeat::No) {
+        playback.playing = !playback.playing;
+        println!("Animation {}", if playback.playing { "playing" } else { "paused" });
+    }
+    if window.is_key_pressed(Key::K, KeyRepeat::No) && rig.skin.is_some() {
+        *method = match method {
+            SkinningMethod::LinearBlend => SkinningMethod::DualQuaternion,
+            SkinningMethod::DualQuaternion => SkinningMethod::LinearBlend,
+        };
+        println!("Skinning: {:?}", method);
+    }
+    // a step of a tenth of a second, repeated while the key is held
+    let mut scrub = 0.0;
+    if window.is_key_pressed(Key::Left, KeyRepeat::Yes) {
+        scrub -= 0.1;
+    }
+    if window.is_key_pressed(Key::Right, KeyRepeat::Yes) {
+        scrub += 0.1;
+    }
+    if scrub != 0.0 {
+        playback.seek(playback.time + scrub, duration);
+        println!("Animation time: {:.2} / {:.2}", playback.time, duration);
+    }
+    playback.advance(dt, duration);
+}
+
+// Nearest triangle under the cursor of the copies of `mesh` drawn with `models`, with the index
+// of the copy it belongs to, reported on the console. The copies may be scaled differently,
+// so the hits are compared by their distance in world space.
+pub fn pick(mesh: &Mesh, bvh: &Bvh, camera: &Camera, models: &[Mat4], mouse_pos: (f32, f32)) -> Option<(usize, Hit)> {
+    let ray = camera.pixel_ray(mouse_pos.0, mouse_pos.1, WIDTH_F, HEIGHT_F);
+    let nearest = models
+        .iter()
+        .enumerate()
+        .filter_map(|(instance, model)| {
+            let hit = bvh.intersect(mesh, &ray.transform(&model.inverse()))?;
+            let position = model.transform_point3(hit.position);
+            Some((instance, hit, position, position.distance_squared(ray.origin)))
+        })
+        .min_by(|a, b| a.3.total_cmp(&b.3));
+    let Some((instance, hit, position, _)) = nearest else {
+        println!("Nothing picked");
+        return None;
+    };
+    if models.len() > 1 {
+        println!(
+            "Picked triangle {} of instance {} at {:?}, uv {:?}, barycentric {:?}",
+            hit.triangle, instance, position, hit.uv, hit.barycentric,
+        );
+    } else {
+        println!(
+            "Picked triangle {} at {:?}, uv {:?}, barycentric {:?}",
+            hit.triangle, position, hit.uv, hit.barycentric,
         );
+    }
+    Some((instance, hit))
+}
 
-        window.update_with_buffer(&buffer, WIDTH, HEIGHT).unwrap();
+// resolves the render target, applies the post effects and shows the result
+fn present_frame(
+    target: &mut Framebuffer,
+    output: &mut Framebuffer,
+    anti_aliasing: AntiAliasing,
+    post_stack: &mut PostStack,
+    camera: &Camera,
+    window: &mut Window,
+) {
+    target.resolve();
+    target.resolve_debug_view(camera);
+
+    let frame = if anti_aliasing.scale() > 1 {
+        target.downsample_into(output);
+        output
+    } else {
+        target
+    };
+
+    post_stack.apply(frame, camera);
+
+    window.update_with_buffer(&frame.color, WIDTH, HEIGHT).unwrap();
+}
+
+// state of the wireframe overlay drawn on top of the shaded mesh,
+// the bias pulls the edges in front of the triangles they belong to
+pub fn wireframe_state(state: &DrawState, reverse_z: bool) -> DrawState {
+    DrawState {
+        blend: BlendMode::Opaque,
+        opacity: 1.0,
+        depth: DepthState {
+            write: false,
+            bias: if reverse_z { WIREFRAME_BIAS } else { -WIREFRAME_BIAS },
+            ..state.depth
+        },
+        polygon_mode: PolygonMode::Line,
+        line_smooth: true,
+        lit: false,
+        ..*state
+    }
+}
+
+// number keys toggle the post effects by name, a key does nothing when its effect is missing
+pub fn handle_post_stack(post_stack: &mut PostStack, window: &Window) {
+    const KEYS: [(Key, &str); 9] = [
+        (Key::Key1, "FXAA"),
+        (Key::Key2, "Bloom"),
+        (Key::Key3, "Vignette"),
+        (Key::Key4, "Color grading"),
+        (Key::Key5, "Sharpen"),
+        (Key::Key6, "Chromatic aberration"),
+        (Key::Key7, "SSAO"),
+        (Key::Key8, "Fog"),
+        (Key::Key9, "Depth of field"),
+    ];
+    for (key, name) in KEYS {
+        if !window.is_key_pressed(key, KeyRepeat::No) {
+            continue;
+        }
+        if let Some(index) = post_stack.find(name) {
+            let enabled = post_stack.toggle(index);
+            println!("{}: {}", name, if enabled { "on" } else { "off" });
+        }
+    }
+}
+
+// M cycles through the anti-aliasing modes, returns true when the mode has changed
+pub fn handle_anti_aliasing(anti_aliasing: &mut AntiAliasing, window: &Window) -> bool {
+    if !window.is_key_pressed(Key::M, KeyRepeat::No) {
+        return false;
+    }
+    *anti_aliasing = match *anti_aliasing {
+        AntiAliasing::Off => AntiAliasing::Msaa(2),
+        AntiAliasing::Msaa(2) => AntiAliasing::Msaa(4),
+        AntiAliasing::Msaa(4) => AntiAliasing::Msaa(8),
+        AntiAliasing::Msaa(_) => AntiAliasing::Ssaa(2),
+        AntiAliasing::Ssaa(_) => AntiAliasing::Off,
+    };
+    println!("Anti-aliasing: {:?}", anti_aliasing);
+    true
+}
+
+// T cycles through the blend modes
+pub fn handle_draw_state(state: &mut DrawState, window: &Window) {
+    if window.is_key_pressed(Key::T, KeyRepeat::No) {
+        (state.blend, state.opacity) = match state.blend {
+            BlendMode::Opaque => (BlendMode::Alpha, 0.5),
+            BlendMode::Alpha => (BlendMode::ABuffer, 0.5),
+            BlendMode::ABuffer => (BlendMode::Opaque, 1.0),
+        };
+        // transparent surfaces are depth tested but must not hide each other
+        state.depth.write = state.blend == BlendMode::Opaque;
+        println!("Blend mode: {:?}", state.blend);
+    }
+}
+
+// H cycles through no hierarchical depth, hierarchical depth, and hierarchical depth with a depth pre-pass
+pub fn handle_hiz(hiz_enabled: &mut bool, depth_prepass: &mut bool, target: &mut Framebuffer, window: &Window) {
+    if window.is_key_pressed(Key::H, KeyRepeat::No) {
+        (*hiz_enabled, *depth_prepass) = match (*hiz_enabled, *depth_prepass) {
+            (false, _) => (true, false),
+            (true, false) => (true, true),
+            (true, true) => (false, false),
+        };
+        println!("Hierarchical Z: {}, depth pre-pass: {}", hiz_enabled, depth_prepass);
+    }
+    if target.hiz.is_some() != *hiz_enabled {
+        if *hiz_enabled {
+            target.enable_hiz(hiz::DEFAULT_TILE_SIZE);
+        } else {
+            target.disable_hiz();
+        }
+    }
+}
+
+// L cycles through unlit, forward and deferred shading, the lights follow the camera and the time
+pub fn handle_shading(shading: &mut Shading, target: &mut Framebuffer, camera: &Camera, time: f32, window: &Window) {
+    if window.is_key_pressed(Key::L, KeyRepeat::No) {
+        *shading = match *shading {
+            Shading::Unlit => Shading::Forward,
+            Shading::Forward => Shading::Deferred,
+            Shading::Deferred => Shading::Unlit,
+        };
+        println!("Shading: {:?}", shading);
+    }
+    if target.g_buffer.is_some() != (*shading == Shading::Deferred) {
+        if *shading == Shading::Deferred {
+            target.enable_g_buffer();
+        } else {
+            target.disable_g_buffer();
+        }
+    }
+    target.lighting = (*shading != Shading::Unlit)
+        .then(|| Lighting::new(camera, lighting::light_ring(LIGHT_COUNT, LIGHT_RING_RADIUS, time), AMBIENT));
+}
+
+// V cycles through the debug views and back to the shaded image, the view normals follow the camera
+pub fn handle_debug_view(debug_mode: &mut Option<DebugMode>, target: &mut Framebuffer, camera: &Camera, window: &Window) {
+    if window.is_key_pressed(Key::V, KeyRepeat::No) {
+        *debug_mode = DebugMode::next(*debug_mode);
+        match debug_mode {
+            Some(mode) => println!("Debug view: {:?}", mode),
+            None => println!("Debug view: off"),
+        }
+    }
+    match (*debug_mode, target.debug_view.as_mut()) {
+        (None, Some(_)) => target.disable_debug_view(),
+        (Some(mode), Some(debug_view)) if debug_view.mode == mode => debug_view.view = glam::Mat3::from_mat4(camera.view()),
+        (Some(mode), _) => target.enable_debug_view(mode, camera),
+        (None, None) => {}
+    }
+}
+
+// Z toggles the reverse-Z infinite projection
+pub fn handle_reverse_z(camera: &mut Camera, state: &mut DrawState, target: &mut Framebuffer, window: &Window) {
+    if window.is_key_pressed(Key::Z, KeyRepeat::No) {
+        camera.reverse_z = !camera.reverse_z;
+        println!("Reverse-Z: {}", camera.reverse_z);
+    }
+    if target.reverse_z != camera.reverse_z {
+        target.set_reverse_z(camera.reverse_z);
+        state.depth.compare = if camera.reverse_z { DepthState::reversed().compare } else { DepthState::default().compare };
     }
 }
 
@@ -0,0 +1,182 @@
+use glam::{Affine2, Mat2, Vec2, Vec3};
+use std::path::{Path, PathBuf};
+
+use crate::error::{LoadError, Warnings};
+use crate::texture::Texture;
+use crate::utils::parse_floats;
+
+// Surface description of a group of triangles, as found in `.mtl` files
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub name: String,
+    pub diffuse: Vec3,  // Kd
+    pub specular: Vec3, // Ks
+    pub shininess: f32, // Ns
+    pub opacity: f32,   // d, or 1 - Tr
+    pub emissive: Vec3, // Ke, already multiplied with the emissive strength
+    pub diffuse_map: Option<PathBuf>,
+    // encoded image of a diffuse map embedded into the file, like the ones of `.glb` files
+    pub diffuse_image: Option<Vec<u8>>,
+    pub bump_map: Option<PathBuf>,
+    // texture coordinate set of the diffuse map and its transform,
+    // both are already applied to the vertices of the mesh
+    pub uv_set: u32,
+    pub uv_transform: Affine2,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            diffuse: Vec3::ONE,
+            specular: Vec3::ZERO,
+            shininess: 0.0,
+            opacity: 1.0,
+            emissive: Vec3::ZERO,
+            diffuse_map: None,
+            diffuse_image: None,
+            bump_map: None,
+            uv_set: 0,
+            uv_transform: Affine2::IDENTITY,
+        }
+    }
+}
+
+impl Material {
+    pub fn load_mtl(path: &Path) -> Result<Vec<Material>, LoadError> {
+        let source = std::fs::read_to_string(path)?;
+        parse_mtl(&source, path.parent().unwrap_or(Path::new("")))
+    }
+
+    // External textures are resolved against `directory`, embedded ones are read from `buffers`
+    // or decoded from their `data:` URI. A texture that cannot be read is left out with a warning.
+    pub fn from_gltf(
+        material: &gltf::Material,
+        directory: &Path,
+        buffers: &[gltf::buffer::Data],
+        warnings: &mut Warnings,
+    ) -> Self {
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, a] = pbr.base_color_factor();
+        let base_color = pbr.base_color_texture();
+
+        let mut diffuse_map = None;
+        let mut diffuse_image = None;
+        if let Some(info) = base_color.as_ref() {
+            match info.texture().source().source() {
+                gltf::image::Source::Uri { uri, .. } if uri.starts_with("data:") => match decode_data_uri(uri) {
+                    Ok(bytes) => diffuse_image = Some(bytes),
+                    Err(e) => warnings.push(format!("texture of material {:?}: {}", material.name(), e)),
+                },
+                gltf::image::Source::Uri { uri, .. } => diffuse_map = Some(directory.join(uri)),
+                gltf::image::Source::View { view, .. } => match buffers.get(view.buffer().index()) {
+                    Some(buffer) if view.offset() + view.length() <= buffer.len() => {
+                        diffuse_image = Some(buffer[view.offset()..view.offset() + view.length()].to_vec());
+                    }
+                    _ => warnings.push(format!("texture of material {:?} is outside of its buffer", material.name())),
+                },
+            }
+        }
+        let (uv_set, uv_transform) = base_color.as_ref().map_or((0, Affine2::IDENTITY), gltf_uv_transform);
+
+        Self {
+            name: material.name().unwrap_or_default().to_string(),
+            diffuse: glam::vec3(r, g, b),
+            opacity: a,
+            emissive: Vec3::from(material.emissive_factor()) * material.emissive_strength().unwrap_or(1.0),
+            diffuse_map,
+            diffuse_image,
+            uv_set,
+            uv_transform,
+            ..Default::default()
+        }
+    }
+
+    pub fn diffuse_texture(&self) -> Option<Texture> {
+        match (&self.diffuse_map, &self.diffuse_image) {
+            (Some(path), _) => Some(Texture::load(path)),
+            (None, Some(bytes)) => Some(Texture::from_memory(bytes)),
+            (None, None) => None,
+        }
+    }
+}
+
+// bytes of a base64 `data:` URI, like `data:image/png;base64,iVBORw0...`
+fn decode_data_uri(uri: &str) -> Result<Vec<u8>, String> {
+    let (header, data) = uri.split_once(',').ok_or("data URI without data")?;
+    if !header.ends_with(";base64") {
+        return Err(format!("data URI is not base64 encoded: {}", header));
+    }
+    base64::decode(data).map_err(|e| format!("invalid base64 data URI: {}", e))
+}
+
+// texture coordinate set and `KHR_texture_transform` of a texture reference
+pub fn gltf_uv_transform(info: &gltf::texture::Info) -> (u32, Affine2) {
+    let Some(transform) = info.texture_transform() else {
+        return (info.tex_coord(), Affine2::IDENTITY);
+    };
+    // counter-clockwise in texture space, where v points down
+    let (sin, cos) = transform.rotation().sin_cos();
+    let rotation = Mat2::from_cols(glam::vec2(cos, -sin), glam::vec2(sin, cos));
+    let matrix = Affine2::from_translation(Vec2::from(transform.offset()))
+        * Affine2::from_mat2(rotation)
+        * Affine2::from_scale(Vec2::from(transform.scale()));
+    (transform.tex_coord().unwrap_or(info.tex_coord()), matrix)
+}
+
+// a single value is used for all three channels
+fn parse_color(args: &[&str], line: usize) -> Result<Vec3, LoadError> {
+    if args.len() == 1 {
+        let [value] = parse_floats::<1>(args, line)?;
+        return Ok(Vec3::splat(value));
+    }
+    parse_floats::<3>(args, line).map(Vec3::from)
+}
+
+// texture maps can have options like `-bm 1.0` in front of the file name
+fn parse_map(args: &[&str], line: usize, directory: &Path) -> Result<PathBuf, LoadError> {
+    args.last()
+        .map(|file| directory.join(file))
+        .ok_or_else(|| LoadError::parse(line, "missing texture file"))
+}
+
+// relative texture paths are resolved against `directory`
+pub fn parse_mtl(source: &str, directory: &Path) -> Result<Vec<Material>, LoadError> {
+    let mut materials: Vec<Material> = Vec::new();
+
+    for (number, text) in source.lines().enumerate() {
+        let line = number + 1;
+        let text = text.split('#').next().unwrap_or("").trim();
+        let mut tokens = text.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            materials.push(Material {
+                name: args.join(" "),
+                ..Default::default()
+            });
+            continue;
+        }
+        let Some(material) = materials.last_mut() else {
+            return Err(LoadError::parse(line, format!("'{}' before newmtl", keyword)));
+        };
+
+        match keyword {
+            "Kd" => material.diffuse = parse_color(&args, line)?,
+            "Ks" => material.specular = parse_color(&args, line)?,
+            "Ke" => material.emissive = parse_color(&args, line)?,
+            "Ns" => material.shininess = parse_floats::<1>(&args, line)?[0],
+            "d" => material.opacity = parse_floats::<1>(&args, line)?[0],
+            "Tr" => material.opacity = 1.0 - parse_floats::<1>(&args, line)?[0],
+            "map_Kd" => material.diffuse_map = Some(parse_map(&args, line, directory)?),
+            "map_Bump" | "map_bump" | "bump" => material.bump_map = Some(parse_map(&args, line, directory)?),
+            // ambient, illumination models and the remaining maps are not used
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
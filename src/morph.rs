@@ -0,0 +1,82 @@
+use glam::Vec3;
+
+use crate::geometry::Vertex;
+
+// Blend shape of a mesh, one delta per vertex for each attribute it moves.
+// An attribute the target does not move has no deltas.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MorphTarget {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    // kept for meshes with normal maps, the vertices themselves have no tangents
+    pub tangents: Vec<Vec3>,
+}
+
+impl MorphTarget {
+    fn attributes_mut(&mut self) -> [&mut Vec<Vec3>; 3] {
+        [&mut self.positions, &mut self.normals, &mut self.tangents]
+    }
+
+    // deltas of the vertices `sources`, in that order
+    pub fn select(&self, sources: &[usize]) -> Self {
+        let pick = |deltas: &Vec<Vec3>| {
+            if deltas.is_empty() {
+                Vec::new()
+            } else {
+                sources.iter().map(|i| deltas[*i]).collect()
+            }
+        };
+        Self {
+            positions: pick(&self.positions),
+            normals: pick(&self.normals),
+            tangents: pick(&self.tangents),
+        }
+    }
+
+    // appends the deltas of `other` after `count` vertices, filling the gaps with zeros
+    pub fn append(&mut self, count: usize, other: &Self) {
+        let others = [&other.positions, &other.normals, &other.tangents];
+        for (deltas, other) in self.attributes_mut().into_iter().zip(others) {
+            if deltas.is_empty() && other.is_empty() {
+                continue;
+            }
+            deltas.resize(count, Vec3::ZERO);
+            deltas.extend_from_slice(other);
+        }
+    }
+
+    // vertices added without deltas are not moved by the target
+    pub fn pad(&mut self, count: usize) {
+        for deltas in self.attributes_mut() {
+            if !deltas.is_empty() {
+                deltas.resize(count, Vec3::ZERO);
+            }
+        }
+    }
+
+    pub fn same_deltas(&self, a: usize, b: usize) -> bool {
+        [&self.positions, &self.normals, &self.tangents]
+            .iter()
+            .all(|deltas| deltas.is_empty() || deltas[a] == deltas[b])
+    }
+}
+
+// Vertex stage blending, each target moves the vertices by its deltas times its weight
+pub fn morph_vertices(vertices: &[Vertex], targets: &[MorphTarget], weights: &[f32]) -> Vec<Vertex> {
+    let mut morphed = vertices.to_vec();
+    for (target, weight) in targets.iter().zip(weights) {
+        if *weight == 0.0 {
+            continue;
+        }
+        for (vertex, delta) in morphed.iter_mut().zip(&target.positions) {
+            vertex.pos += *delta * *weight;
+        }
+        for (vertex, delta) in morphed.iter_mut().zip(&target.normals) {
+            vertex.normal += *delta * *weight;
+        }
+    }
+    if targets.iter().any(|target| !target.normals.is_empty()) {
+        morphed.iter_mut().for_each(|vertex| vertex.normal = vertex.normal.normalize_or_zero());
+    }
+    morphed
+}
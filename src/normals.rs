@@ -0,0 +1,120 @@
+use glam::{UVec3, Vec3};
+use std::collections::HashMap;
+
+use crate::geometry::Vertex;
+
+// faces meeting at a sharper angle keep a hard edge
+pub const DEFAULT_CREASE_ANGLE: f32 = std::f32::consts::FRAC_PI_3;
+
+// normals closer than this are considered the same and share a vertex
+const SAME_NORMAL_COS: f32 = 0.9999;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NormalMode {
+    // every triangle uses its face normal
+    Flat,
+    // corners at the same position average the faces around them, weighted by
+    // the angle of the corner, faces differing by more than `crease_angle` (radians) are left out
+    Smooth { crease_angle: f32 },
+}
+
+impl Default for NormalMode {
+    fn default() -> Self {
+        NormalMode::Smooth {
+            crease_angle: DEFAULT_CREASE_ANGLE,
+        }
+    }
+}
+
+// unit normal of a counter-clockwise triangle, zero for degenerate ones
+pub fn face_normal(a: Vec3, b: Vec3, c: Vec3) -> Vec3 {
+    (b - a).cross(c - a).normalize_or_zero()
+}
+
+// angle of the triangle at its corner `a`
+fn corner_angle(a: Vec3, b: Vec3, c: Vec3) -> f32 {
+    let (ab, ac) = ((b - a).normalize_or_zero(), (c - a).normalize_or_zero());
+    ab.dot(ac).clamp(-1.0, 1.0).acos()
+}
+
+// normal of each corner of `triangles`, three per triangle
+pub fn corner_normals(triangles: &[UVec3], vertices: &[Vertex], mode: NormalMode) -> Vec<Vec3> {
+    let corners = |t: &UVec3| t.to_array().map(|i| vertices[i as usize].pos);
+    let faces: Vec<Vec3> = triangles.iter().map(|t| {
+        let [a, b, c] = corners(t);
+        face_normal(a, b, c)
+    }).collect();
+
+    let crease_angle = match mode {
+        NormalMode::Flat => return faces.iter().flat_map(|n| [*n; 3]).collect(),
+        NormalMode::Smooth { crease_angle } => crease_angle,
+    };
+    let min_cos = crease_angle.cos();
+
+    // corners are grouped by position rather than by vertex, so seams of the
+    // texture coordinates do not show up in the shading
+    let mut corners_at: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+    for (t, triangle) in triangles.iter().enumerate() {
+        for (k, index) in triangle.to_array().iter().enumerate() {
+            let key = vertices[*index as usize].pos.to_array().map(f32::to_bits);
+            corners_at.entry(key).or_default().push(t * 3 + k);
+        }
+    }
+
+    let weights: Vec<f32> = triangles.iter().flat_map(|t| {
+        let [a, b, c] = corners(t);
+        [corner_angle(a, b, c), corner_angle(b, c, a), corner_angle(c, a, b)]
+    }).collect();
+
+    let mut normals = vec![Vec3::ZERO; triangles.len() * 3];
+    for shared in corners_at.values() {
+        for &corner in shared {
+            let face = faces[corner / 3];
+            let sum: Vec3 = shared
+                .iter()
+                .filter(|other| {
+                    let other_face = faces[**other / 3];
+                    **other / 3 == corner / 3 || face.dot(other_face) >= min_cos
+                })
+                .map(|other| faces[*other / 3] * weights[*other])
+                .sum();
+            normals[corner] = sum.normalize_or(face);
+        }
+    }
+    normals
+}
+
+// Writes the corner normals into the vertices. A vertex whose corners ended up with
+// different normals (on a crease, or everywhere for flat shading) is duplicated,
+// the vertices the copies were made from are returned in the order they were appended.
+pub fn apply_corner_normals(triangles: &mut [UVec3], vertices: &mut Vec<Vertex>, normals: &[Vec3]) -> Vec<u32> {
+    // vertex each of the appended copies was made from
+    let mut sources = Vec::new();
+    let mut copies: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (t, triangle) in triangles.iter_mut().enumerate() {
+        let mut indices = triangle.to_array();
+        for (k, index) in indices.iter_mut().enumerate() {
+            let normal = normals[t * 3 + k];
+            let copies = copies.entry(*index).or_default();
+            let same = copies.iter().find(|i| vertices[**i as usize].normal.dot(normal) >= SAME_NORMAL_COS);
+            *index = match same {
+                Some(copy) => *copy,
+                None if copies.is_empty() => {
+                    vertices[*index as usize].normal = normal;
+                    copies.push(*index);
+                    *index
+                }
+                None => {
+                    let mut vertex = vertices[*index as usize];
+                    vertex.normal = normal;
+                    vertices.push(vertex);
+                    sources.push(*index);
+                    copies.push(vertices.len() as u32 - 1);
+                    vertices.len() as u32 - 1
+                }
+            };
+        }
+        *triangle = UVec3::from_array(indices);
+    }
+    sources
+}
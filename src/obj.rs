@@ -0,0 +1,211 @@
+use glam::{UVec2, UVec3, Vec2, Vec3};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::error::{LoadError, Warnings};
+use crate::geometry::{triangulate_polygon, Mesh, MeshGroup};
+use crate::material::Material;
+use crate::utils::parse_floats;
+
+// Indices of a `v/vt/vn` triple, 0 based, -1 when the element is missing
+type Corner = (i64, i64, i64);
+
+pub fn load_obj(path: &Path) -> Result<(Mesh, Vec<Material>, Warnings), LoadError> {
+    let source = std::fs::read_to_string(path)?;
+    parse_obj(&source, path.parent().unwrap_or(Path::new("")))
+}
+
+// `.mtl` libraries are loaded relative to `directory`. Missing materials and libraries
+// and unknown statements are skipped and reported in the warnings.
+pub fn parse_obj(source: &str, directory: &Path) -> Result<(Mesh, Vec<Material>, Warnings), LoadError> {
+    let mut builder = ObjBuilder::default();
+
+    for (number, text) in source.lines().enumerate() {
+        let line = number + 1;
+        let text = text.split('#').next().unwrap_or("").trim();
+        let mut tokens = text.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                builder.positions.push(Vec3::from(parse_floats::<3>(&args, line)?));
+                // vertex colors are a common extension, `v x y z r g b`
+                if args.len() >= 6 {
+                    let [_, _, _, r, g, b] = parse_floats::<6>(&args, line)?;
+                    builder.colors.resize(builder.positions.len() - 1, Vec3::ONE);
+                    builder.colors.push(glam::vec3(r, g, b));
+                }
+            }
+            "vt" => {
+                let uv = if args.len() == 1 { [parse_floats::<1>(&args, line)?[0], 0.0] } else { parse_floats::<2>(&args, line)? };
+                // OBJ puts the origin of the texture at the bottom
+                builder.uvs.push(glam::vec2(uv[0], 1.0 - uv[1]));
+            }
+            "vn" => builder.normals.push(Vec3::from(parse_floats::<3>(&args, line)?)),
+            "f" => {
+                let corners = builder.parse_corners(&args, line)?;
+                if corners.len() < 3 {
+                    return Err(LoadError::parse(line, "face with less than 3 vertices"));
+                }
+                let indices: Vec<u32> = corners.iter().map(|corner| builder.vertex(*corner)).collect();
+                builder.add_polygon(&indices);
+            }
+            "l" => {
+                let corners = builder.parse_corners(&args, line)?;
+                let indices: Vec<u32> = corners.iter().map(|corner| builder.vertex(*corner)).collect();
+                builder.lines.extend(indices.windows(2).map(|l| UVec2::new(l[0], l[1])));
+            }
+            "p" => {
+                let corners = builder.parse_corners(&args, line)?;
+                for corner in corners {
+                    let index = builder.vertex(corner);
+                    builder.points.push(index);
+                }
+            }
+            "o" | "g" => builder.begin_group(args.join(" "), builder.material),
+            "usemtl" => {
+                let name = args.join(" ");
+                let material = builder.materials.iter().position(|m| m.name == name);
+                if material.is_none() {
+                    builder.warnings.push(format!("line {}: material '{}' not found", line, name));
+                }
+                let group = builder.group_name.clone();
+                builder.begin_group(group, material);
+            }
+            "mtllib" => {
+                for file in &args {
+                    match Material::load_mtl(&directory.join(file)) {
+                        Ok(materials) => builder.materials.extend(materials),
+                        Err(error) => builder.warnings.push(format!("material library {}: {}", file, error)),
+                    }
+                }
+            }
+            // smoothing groups are not supported
+            "s" => {}
+            _ => builder.warnings.push(format!("line {}: unknown statement '{}'", line, keyword)),
+        }
+    }
+
+    Ok(builder.finish())
+}
+
+#[derive(Default)]
+struct ObjBuilder {
+    positions: Vec<Vec3>,
+    uvs: Vec<Vec2>,
+    normals: Vec<Vec3>,
+    colors: Vec<Vec3>, // per position, empty without vertex colors
+    // unique `v/vt/vn` triples, each one becomes a vertex of the mesh
+    corners: Vec<Corner>,
+    vertex_of_corner: HashMap<Corner, u32>,
+    triangles: Vec<UVec3>,
+    lines: Vec<UVec2>,
+    points: Vec<u32>,
+    materials: Vec<Material>,
+    groups: Vec<MeshGroup>,
+    group_name: String,
+    group_start: usize,
+    material: Option<usize>,
+    warnings: Warnings,
+}
+
+impl ObjBuilder {
+    // resolves 1 based and negative (relative to the end) indices
+    fn resolve(index: &str, count: usize, line: usize) -> Result<i64, LoadError> {
+        if index.is_empty() {
+            return Ok(-1);
+        }
+        let value: i64 = index
+            .parse()
+            .map_err(|_| LoadError::parse(line, format!("invalid index '{}'", index)))?;
+        let resolved = if value < 0 { count as i64 + value } else { value - 1 };
+        if resolved < 0 || resolved >= count as i64 {
+            return Err(LoadError::parse(line, format!("index {} out of range", value)));
+        }
+        Ok(resolved)
+    }
+
+    fn parse_corners(&self, args: &[&str], line: usize) -> Result<Vec<Corner>, LoadError> {
+        args.iter()
+            .map(|arg| {
+                let mut parts = arg.split('/');
+                let position = Self::resolve(parts.next().unwrap_or(""), self.positions.len(), line)?;
+                if position < 0 {
+                    return Err(LoadError::parse(line, "vertex without a position"));
+                }
+                let uv = Self::resolve(parts.next().unwrap_or(""), self.uvs.len(), line)?;
+                let normal = Self::resolve(parts.next().unwrap_or(""), self.normals.len(), line)?;
+                Ok((position, uv, normal))
+            })
+            .collect()
+    }
+
+    fn vertex(&mut self, corner: Corner) -> u32 {
+        *self.vertex_of_corner.entry(corner).or_insert_with(|| {
+            self.corners.push(corner);
+            self.corners.len() as u32 - 1
+        })
+    }
+
+    fn add_polygon(&mut self, indices: &[u32]) {
+        let positions: Vec<Vec3> = indices
+            .iter()
+            .map(|i| self.positions[self.corners[*i as usize].0 as usize])
+            .collect();
+        for [a, b, c] in triangulate_polygon(&positions) {
+            self.triangles.push(UVec3::new(indices[a], indices[b], indices[c]));
+        }
+    }
+
+    fn close_group(&mut self) {
+        if self.triangles.len() > self.group_start {
+            self.groups.push(MeshGroup {
+                name: self.group_name.clone(),
+                triangles: self.group_start..self.triangles.len(),
+                material: self.material,
+            });
+        }
+    }
+
+    fn begin_group(&mut self, name: String, material: Option<usize>) {
+        self.close_group();
+        self.group_name = name;
+        self.group_start = self.triangles.len();
+        self.material = material;
+    }
+
+    fn finish(mut self) -> (Mesh, Vec<Material>, Warnings) {
+        self.close_group();
+
+        let positions: Vec<Vec3> = self.corners.iter().map(|c| self.positions[c.0 as usize]).collect();
+        let uvs: Vec<Vec2> = self
+            .corners
+            .iter()
+            .map(|c| if c.1 >= 0 { self.uvs[c.1 as usize] } else { Vec2::ZERO })
+            .collect();
+        // normals are generated for the whole mesh unless every vertex has one
+        let normals: Vec<Vec3> = if self.corners.iter().all(|c| c.2 >= 0) {
+            self.corners.iter().map(|c| self.normals[c.2 as usize]).collect()
+        } else {
+            Vec::new()
+        };
+
+        let colors: Vec<Vec3> = self
+            .corners
+            .iter()
+            .map(|c| self.colors.get(c.0 as usize).copied().unwrap_or(Vec3::ONE))
+            .collect();
+
+        let mut mesh = Mesh::new();
+        mesh.add_section_from_buffers(&self.triangles, &positions, &normals, &colors, &uvs);
+        mesh.add_lines(&self.lines);
+        mesh.add_points(&self.points);
+        for group in self.groups {
+            mesh.add_group(group);
+        }
+        (mesh, self.materials, self.warnings)
+    }
+}
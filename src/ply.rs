@@ -0,0 +1,297 @@
+use glam::{UVec3, Vec2, Vec3};
+use std::path::Path;
+
+use crate::error::LoadError;
+use crate::geometry::{triangulate_polygon, Mesh};
+
+pub fn load_ply(path: &Path) -> Result<Mesh, LoadError> {
+    let data = std::fs::read(path)?;
+    parse_ply(&data)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    // integer colors go up to the maximum of their type, floating point ones to 1
+    fn color_scale(self) -> f64 {
+        match self {
+            Scalar::I8 => 1.0 / i8::MAX as f64,
+            Scalar::U8 => 1.0 / u8::MAX as f64,
+            Scalar::I16 => 1.0 / i16::MAX as f64,
+            Scalar::U16 => 1.0 / u16::MAX as f64,
+            Scalar::I32 => 1.0 / i32::MAX as f64,
+            Scalar::U32 => 1.0 / u32::MAX as f64,
+            Scalar::F32 | Scalar::F64 => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Property {
+    name: String,
+    scalar: Scalar,
+    // type of the element count of list properties
+    list_count: Option<Scalar>,
+}
+
+#[derive(Debug, Clone)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn property(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|p| names.contains(&p.name.as_str()))
+    }
+}
+
+// Values of the body, read one by one in the order of the header
+enum Body<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], offset: usize, big_endian: bool },
+}
+
+impl Body<'_> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64, LoadError> {
+        match self {
+            Body::Ascii(tokens) => {
+                let token = tokens
+                    .next()
+                    .ok_or_else(|| LoadError::Invalid("unexpected end of PLY data".into()))?;
+                token
+                    .parse()
+                    .map_err(|_| LoadError::Invalid(format!("invalid PLY value '{}'", token)))
+            }
+            Body::Binary { data, offset, big_endian } => {
+                let size = scalar.size();
+                let bytes = data
+                    .get(*offset..*offset + size)
+                    .ok_or_else(|| LoadError::Invalid("unexpected end of PLY data".into()))?;
+                *offset += size;
+
+                let mut buffer = [0u8; 8];
+                buffer[..size].copy_from_slice(bytes);
+                if *big_endian {
+                    buffer[..size].reverse();
+                }
+                let [b0, b1, b2, b3, ..] = buffer;
+                Ok(match scalar {
+                    Scalar::I8 => b0 as i8 as f64,
+                    Scalar::U8 => b0 as f64,
+                    Scalar::I16 => i16::from_le_bytes([b0, b1]) as f64,
+                    Scalar::U16 => u16::from_le_bytes([b0, b1]) as f64,
+                    Scalar::I32 => i32::from_le_bytes([b0, b1, b2, b3]) as f64,
+                    Scalar::U32 => u32::from_le_bytes([b0, b1, b2, b3]) as f64,
+                    Scalar::F32 => f32::from_le_bytes([b0, b1, b2, b3]) as f64,
+                    Scalar::F64 => f64::from_le_bytes(buffer),
+                })
+            }
+        }
+    }
+}
+
+fn parse_header(data: &[u8]) -> Result<(Format, Vec<Element>, usize), LoadError> {
+    const END: &[u8] = b"end_header";
+    let end = data
+        .windows(END.len())
+        .position(|w| w == END)
+        .ok_or_else(|| LoadError::parse(1, "missing end_header"))?;
+    // the body starts after the line break of `end_header`
+    let body_start = data[end..]
+        .iter()
+        .position(|b| *b == b'\n')
+        .map_or(data.len(), |p| end + p + 1);
+    let header = std::str::from_utf8(&data[..end]).map_err(|_| LoadError::parse(1, "header is not text"))?;
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for (number, text) in header.lines().enumerate() {
+        let line = number + 1;
+        let tokens: Vec<&str> = text.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["ply"] if line == 1 => {}
+            _ if line == 1 => return Err(LoadError::parse(line, "not a PLY file")),
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(LoadError::parse(line, format!("unknown format '{}'", name))),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| LoadError::parse(line, format!("invalid element count '{}'", count)))?,
+                properties: Vec::new(),
+            }),
+            ["property", rest @ ..] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| LoadError::parse(line, "property before element"))?;
+                let scalar = |name: &str| {
+                    Scalar::parse(name).ok_or_else(|| LoadError::parse(line, format!("unknown type '{}'", name)))
+                };
+                let property = match rest {
+                    ["list", count, item, name] => Property {
+                        name: name.to_string(),
+                        scalar: scalar(item)?,
+                        list_count: Some(scalar(count)?),
+                    },
+                    [item, name] => Property {
+                        name: name.to_string(),
+                        scalar: scalar(item)?,
+                        list_count: None,
+                    },
+                    _ => return Err(LoadError::parse(line, "invalid property")),
+                };
+                element.properties.push(property);
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            [keyword, ..] => return Err(LoadError::parse(line, format!("unknown header keyword '{}'", keyword))),
+        }
+    }
+
+    let format = format.ok_or_else(|| LoadError::parse(1, "missing format"))?;
+    Ok((format, elements, body_start))
+}
+
+// Vertices with positions, optional normals, colors and texture coordinates, faces of any size
+pub fn parse_ply(data: &[u8]) -> Result<Mesh, LoadError> {
+    let (format, elements, body_start) = parse_header(data)?;
+    let body = &data[body_start..];
+    let mut body = match format {
+        Format::Ascii => Body::Ascii(
+            std::str::from_utf8(body)
+                .map_err(|_| LoadError::Invalid("ASCII PLY body is not text".into()))?
+                .split_ascii_whitespace(),
+        ),
+        Format::BinaryLittleEndian | Format::BinaryBigEndian => Body::Binary {
+            data: body,
+            offset: 0,
+            big_endian: format == Format::BinaryBigEndian,
+        },
+    };
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut colors = Vec::new();
+    let mut uvs = Vec::new();
+    let mut faces: Vec<Vec<u32>> = Vec::new();
+
+    for element in &elements {
+        let find = |names: &[&str]| element.property(names);
+        let position = [find(&["x"]), find(&["y"]), find(&["z"])];
+        let normal = [find(&["nx"]), find(&["ny"]), find(&["nz"])];
+        let color = [find(&["red", "r"]), find(&["green", "g"]), find(&["blue", "b"])];
+        let uv = [find(&["u", "s", "texture_u"]), find(&["v", "t", "texture_v"])];
+        let indices = find(&["vertex_indices", "vertex_index"]);
+
+        let mut values = vec![0.0; element.properties.len()];
+        for _ in 0..element.count {
+            let mut list = Vec::new();
+            for (i, property) in element.properties.iter().enumerate() {
+                match property.list_count {
+                    Some(count) => {
+                        let count = body.read(count)? as usize;
+                        let items = (0..count).map(|_| body.read(property.scalar)).collect::<Result<Vec<f64>, _>>()?;
+                        if Some(i) == indices {
+                            list = items;
+                        }
+                    }
+                    None => values[i] = body.read(property.scalar)?,
+                }
+            }
+
+            let get = |index: Option<usize>| index.map(|i| values[i] as f32);
+            let get3 = |indices: [Option<usize>; 3]| match indices.map(get) {
+                [Some(x), Some(y), Some(z)] => Some(glam::vec3(x, y, z)),
+                _ => None,
+            };
+            match element.name.as_str() {
+                "vertex" => {
+                    positions.push(get3(position).ok_or_else(|| LoadError::Invalid("vertex without a position".into()))?);
+                    if let Some(n) = get3(normal) {
+                        normals.push(n);
+                    }
+                    if let Some(c) = get3(color) {
+                        // every channel by the type of its own property
+                        let scale = color.map(|i| i.map_or(1.0, |i| element.properties[i].scalar.color_scale() as f32));
+                        colors.push(c * Vec3::from(scale));
+                    }
+                    if let [Some(u), Some(v)] = uv.map(get) {
+                        uvs.push(glam::vec2(u, v));
+                    }
+                }
+                "face" => faces.push(list.iter().map(|i| *i as u32).collect()),
+                // edges, materials and other custom elements are skipped
+                _ => {}
+            }
+        }
+    }
+
+    let mut triangles = Vec::new();
+    for face in &faces {
+        if face.len() < 3 {
+            continue;
+        }
+        if let Some(index) = face.iter().find(|i| **i as usize >= positions.len()) {
+            return Err(LoadError::Invalid(format!("face index {} out of range", index)));
+        }
+        let corners: Vec<Vec3> = face.iter().map(|i| positions[*i as usize]).collect();
+        for [a, b, c] in triangulate_polygon(&corners) {
+            triangles.push(UVec3::new(face[a], face[b], face[c]));
+        }
+    }
+
+    // attributes are only kept when every vertex has them
+    let uvs: Vec<Vec2> = if uvs.len() == positions.len() { uvs } else { Vec::new() };
+    let normals: Vec<Vec3> = if normals.len() == positions.len() { normals } else { Vec::new() };
+    let colors: Vec<Vec3> = if colors.len() == positions.len() { colors } else { Vec::new() };
+    let mut mesh = Mesh::new();
+    mesh.add_section_from_buffers(&triangles, &positions, &normals, &colors, &uvs);
+    Ok(mesh)
+}
@@ -0,0 +1,505 @@
+use glam::{Vec2, Vec3};
+use std::path::Path;
+
+use crate::camera::Camera;
+use crate::error::LoadError;
+use crate::framebuffer::Framebuffer;
+use crate::utils::{from_argb8_v, to_argb8_v};
+
+// A full-screen pass working on the resolved framebuffer
+pub trait PostEffect {
+    fn name(&self) -> &'static str;
+    fn apply(&mut self, target: &mut Framebuffer, camera: &Camera);
+}
+
+struct PostPass {
+    effect: Box<dyn PostEffect>,
+    enabled: bool,
+}
+
+// Ordered chain of post effects, every pass can be toggled at runtime
+#[derive(Default)]
+pub struct PostStack {
+    passes: Vec<PostPass>,
+}
+
+impl PostStack {
+    pub fn new() -> Self {
+        Self { passes: Vec::new() }
+    }
+
+    // returns the index of the pass, passes are added disabled
+    pub fn push(&mut self, effect: impl PostEffect + 'static) -> usize {
+        self.passes.push(PostPass {
+            effect: Box::new(effect),
+            enabled: false,
+        });
+        self.passes.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.passes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    pub fn name(&self, index: usize) -> &'static str {
+        self.passes[index].effect.name()
+    }
+
+    // index of the first pass called `name`
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.passes.iter().position(|pass| pass.effect.name() == name)
+    }
+
+    pub fn is_enabled(&self, index: usize) -> bool {
+        self.passes[index].enabled
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        self.passes[index].enabled = enabled;
+    }
+
+    // returns the new state of the pass
+    pub fn toggle(&mut self, index: usize) -> bool {
+        self.passes[index].enabled = !self.passes[index].enabled;
+        self.passes[index].enabled
+    }
+
+    pub fn apply(&mut self, target: &mut Framebuffer, camera: &Camera) {
+        for pass in self.passes.iter_mut().filter(|pass| pass.enabled) {
+            pass.effect.apply(target, camera);
+        }
+    }
+}
+
+// Image helpers, post effects work with colors in 0..1 range
+
+pub fn unpack_pixel(color: u32) -> Vec3 {
+    from_argb8_v(color) / 255.0
+}
+
+pub fn unpack(color: &[u32]) -> Vec<Vec3> {
+    color.iter().map(|c| unpack_pixel(*c)).collect()
+}
+
+pub fn pack(color: Vec3) -> u32 {
+    to_argb8_v(255, (color * 255.0).clamp(Vec3::ZERO, Vec3::splat(255.0)))
+}
+
+pub fn luma(color: Vec3) -> f32 {
+    color.dot(glam::vec3(0.299, 0.587, 0.114))
+}
+
+pub fn fetch(image: &[Vec3], width: usize, height: usize, x: i32, y: i32) -> Vec3 {
+    let x = x.clamp(0, width as i32 - 1) as usize;
+    let y = y.clamp(0, height as i32 - 1) as usize;
+    image[x + y * width]
+}
+
+// `p` is in pixels, pixel centers are at .5
+pub fn sample_bilinear(image: &[Vec3], width: usize, height: usize, p: Vec2) -> Vec3 {
+    let p = p - Vec2::splat(0.5);
+    let base = p.floor();
+    let f = p - base;
+    let (x, y) = (base.x as i32, base.y as i32);
+
+    let top = fetch(image, width, height, x, y).lerp(fetch(image, width, height, x + 1, y), f.x);
+    let bottom = fetch(image, width, height, x, y + 1).lerp(fetch(image, width, height, x + 1, y + 1), f.x);
+    top.lerp(bottom, f.y)
+}
+
+pub fn gaussian_kernel(radius: usize) -> Vec<f32> {
+    let sigma = (radius as f32 / 2.0).max(0.5);
+    let weights: Vec<f32> = (-(radius as i32)..=radius as i32)
+        .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = weights.iter().sum();
+    weights.iter().map(|w| w / sum).collect()
+}
+
+// separable gaussian blur
+pub fn blur(image: &[Vec3], width: usize, height: usize, radius: usize) -> Vec<Vec3> {
+    let kernel = gaussian_kernel(radius);
+    let r = radius as i32;
+
+    let mut horizontal = vec![Vec3::ZERO; image.len()];
+    for y in 0..height {
+        for x in 0..width {
+            horizontal[x + y * width] = kernel.iter().enumerate().fold(Vec3::ZERO, |sum, (i, w)| {
+                sum + fetch(image, width, height, x as i32 + i as i32 - r, y as i32) * *w
+            });
+        }
+    }
+
+    let mut result = vec![Vec3::ZERO; image.len()];
+    for y in 0..height {
+        for x in 0..width {
+            result[x + y * width] = kernel.iter().enumerate().fold(Vec3::ZERO, |sum, (i, w)| {
+                sum + fetch(&horizontal, width, height, x as i32, y as i32 + i as i32 - r) * *w
+            });
+        }
+    }
+    result
+}
+
+pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Fast approximate anti-aliasing, luma based edge search along the gradient
+pub struct Fxaa {
+    pub span_max: f32,
+    pub reduce_mul: f32,
+    pub reduce_min: f32,
+}
+
+impl Default for Fxaa {
+    fn default() -> Self {
+        Self {
+            span_max: 8.0,
+            reduce_mul: 1.0 / 8.0,
+            reduce_min: 1.0 / 128.0,
+        }
+    }
+}
+
+impl PostEffect for Fxaa {
+    fn name(&self) -> &'static str {
+        "FXAA"
+    }
+
+    fn apply(&mut self, target: &mut Framebuffer, _camera: &Camera) {
+        let (width, height) = (target.width, target.height);
+        let image = unpack(&target.color);
+
+        for y in 0..height {
+            for x in 0..width {
+                let (xi, yi) = (x as i32, y as i32);
+                let luma_nw = luma(fetch(&image, width, height, xi - 1, yi - 1));
+                let luma_ne = luma(fetch(&image, width, height, xi + 1, yi - 1));
+                let luma_sw = luma(fetch(&image, width, height, xi - 1, yi + 1));
+                let luma_se = luma(fetch(&image, width, height, xi + 1, yi + 1));
+                let luma_m = luma(image[x + y * width]);
+
+                let luma_min = luma_m.min(luma_nw.min(luma_ne).min(luma_sw.min(luma_se)));
+                let luma_max = luma_m.max(luma_nw.max(luma_ne).max(luma_sw.max(luma_se)));
+
+                let mut dir = glam::vec2(
+                    -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+                    (luma_nw + luma_sw) - (luma_ne + luma_se),
+                );
+
+                let dir_reduce = ((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * self.reduce_mul)
+                    .max(self.reduce_min);
+                let rcp_dir_min = 1.0 / (dir.x.abs().min(dir.y.abs()) + dir_reduce);
+                dir = (dir * rcp_dir_min).clamp(Vec2::splat(-self.span_max), Vec2::splat(self.span_max));
+
+                let p = glam::vec2(x as f32 + 0.5, y as f32 + 0.5);
+                let rgb_a = 0.5
+                    * (sample_bilinear(&image, width, height, p + dir * (1.0 / 3.0 - 0.5))
+                        + sample_bilinear(&image, width, height, p + dir * (2.0 / 3.0 - 0.5)));
+                let rgb_b = rgb_a * 0.5
+                    + 0.25
+                        * (sample_bilinear(&image, width, height, p - dir * 0.5)
+                            + sample_bilinear(&image, width, height, p + dir * 0.5));
+
+                let luma_b = luma(rgb_b);
+                let result = if luma_b < luma_min || luma_b > luma_max { rgb_a } else { rgb_b };
+                target.color[x + y * width] = pack(result);
+            }
+        }
+    }
+}
+
+// Bright parts of the HDR buffer (or of the color buffer without HDR) blurred and added on top
+pub struct Bloom {
+    pub threshold: f32,
+    pub intensity: f32,
+    pub radius: usize,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self {
+            threshold: 0.8,
+            intensity: 0.6,
+            radius: 8,
+        }
+    }
+}
+
+impl PostEffect for Bloom {
+    fn name(&self) -> &'static str {
+        "Bloom"
+    }
+
+    fn apply(&mut self, target: &mut Framebuffer, _camera: &Camera) {
+        let (width, height) = (target.width, target.height);
+        let source = if target.hdr.is_empty() { unpack(&target.color) } else { target.hdr.clone() };
+
+        let bright: Vec<Vec3> = source
+            .iter()
+            .map(|c| {
+                let l = luma(*c);
+                if l <= self.threshold { Vec3::ZERO } else { *c * ((l - self.threshold) / l) }
+            })
+            .collect();
+
+        let glow = blur(&bright, width, height, self.radius);
+
+        for (color, glow) in target.color.iter_mut().zip(glow.iter()) {
+            *color = pack(unpack_pixel(*color) + *glow * self.intensity);
+        }
+    }
+}
+
+pub struct Vignette {
+    pub intensity: f32,
+    pub radius: f32,   // where the darkening starts, 1.0 is the corner
+    pub softness: f32,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Self {
+            intensity: 0.6,
+            radius: 0.75,
+            softness: 0.5,
+        }
+    }
+}
+
+impl PostEffect for Vignette {
+    fn name(&self) -> &'static str {
+        "Vignette"
+    }
+
+    fn apply(&mut self, target: &mut Framebuffer, _camera: &Camera) {
+        let half = target.size() * 0.5;
+        let corner = half.length();
+        for (index, color) in target.color.iter_mut().enumerate() {
+            let p = glam::vec2((index % target.width) as f32 + 0.5, (index / target.width) as f32 + 0.5);
+            let distance = (p - half).length() / corner;
+            let shade = smoothstep(self.radius, self.radius - self.softness, distance);
+            let factor = 1.0 + (shade - 1.0) * self.intensity;
+            *color = pack(unpack_pixel(*color) * factor);
+        }
+    }
+}
+
+// 3D lookup table in the Adobe/Resolve `.cube` format
+pub struct Lut3d {
+    pub size: usize,
+    pub domain_min: Vec3,
+    pub domain_max: Vec3,
+    pub data: Vec<Vec3>, // red changes fastest
+}
+
+impl Lut3d {
+    pub fn identity(size: usize) -> Self {
+        let step = 1.0 / (size - 1) as f32;
+        let mut data = Vec::with_capacity(size * size * size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.push(glam::vec3(r as f32, g as f32, b as f32) * step);
+                }
+            }
+        }
+        Self {
+            size,
+            domain_min: Vec3::ZERO,
+            domain_max: Vec3::ONE,
+            data,
+        }
+    }
+
+    pub fn load_cube(path: &Path) -> Result<Self, LoadError> {
+        Self::parse_cube(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse_cube(source: &str) -> Result<Self, LoadError> {
+        let mut size = 0;
+        let mut domain_min = Vec3::ZERO;
+        let mut domain_max = Vec3::ONE;
+        let mut data = Vec::new();
+
+        let parse_vec3 = |line: usize, values: &[&str]| -> Result<Vec3, LoadError> {
+            if values.len() != 3 {
+                return Err(LoadError::parse(line, "expected 3 values"));
+            }
+            let mut v = [0.0; 3];
+            for (i, value) in values.iter().enumerate() {
+                v[i] = value
+                    .parse()
+                    .map_err(|_| LoadError::parse(line, format!("invalid number '{}'", value)))?;
+            }
+            Ok(Vec3::from(v))
+        };
+
+        for (number, line) in source.lines().enumerate() {
+            let number = number + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            match tokens[0] {
+                "TITLE" => {}
+                "LUT_1D_SIZE" => return Err(LoadError::Unsupported("1D LUTs".to_string())),
+                "LUT_3D_SIZE" => {
+                    size = tokens
+                        .get(1)
+                        .and_then(|s| s.parse().ok())
+                        .filter(|s| *s >= 2)
+                        .ok_or_else(|| LoadError::parse(number, "invalid LUT_3D_SIZE"))?;
+                }
+                "DOMAIN_MIN" => domain_min = parse_vec3(number, &tokens[1..])?,
+                "DOMAIN_MAX" => domain_max = parse_vec3(number, &tokens[1..])?,
+                // the same domain for all three channels, written by Resolve
+                "LUT_3D_INPUT_RANGE" => {
+                    let range = match tokens[1..] {
+                        [min, max] => min.parse::<f32>().ok().zip(max.parse::<f32>().ok()),
+                        _ => None,
+                    };
+                    let (min, max) = range.ok_or_else(|| LoadError::parse(number, "invalid LUT_3D_INPUT_RANGE"))?;
+                    domain_min = Vec3::splat(min);
+                    domain_max = Vec3::splat(max);
+                }
+                // other keywords of the format and of the applications writing it are skipped
+                keyword if keyword.parse::<f32>().is_err() => {}
+                _ => data.push(parse_vec3(number, &tokens)?),
+            }
+        }
+
+        if size == 0 {
+            return Err(LoadError::parse(0, "missing LUT_3D_SIZE"));
+        }
+        if data.len() != size * size * size {
+            return Err(LoadError::parse(
+                0,
+                format!("expected {} entries, found {}", size * size * size, data.len()),
+            ));
+        }
+
+        Ok(Self {
+            size,
+            domain_min,
+            domain_max,
+            data,
+        })
+    }
+
+    fn at(&self, r: usize, g: usize, b: usize) -> Vec3 {
+        self.data[r + (g + b * self.size) * self.size]
+    }
+
+    // trilinear lookup
+    pub fn sample(&self, color: Vec3) -> Vec3 {
+        let n = (self.size - 1) as f32;
+        let p = ((color - self.domain_min) / (self.domain_max - self.domain_min)).clamp(Vec3::ZERO, Vec3::ONE) * n;
+        let base = p.floor().min(Vec3::splat(n - 1.0));
+        let f = p - base;
+        let (r, g, b) = (base.x as usize, base.y as usize, base.z as usize);
+
+        let c00 = self.at(r, g, b).lerp(self.at(r + 1, g, b), f.x);
+        let c10 = self.at(r, g + 1, b).lerp(self.at(r + 1, g + 1, b), f.x);
+        let c01 = self.at(r, g, b + 1).lerp(self.at(r + 1, g, b + 1), f.x);
+        let c11 = self.at(r, g + 1, b + 1).lerp(self.at(r + 1, g + 1, b + 1), f.x);
+
+        c00.lerp(c10, f.y).lerp(c01.lerp(c11, f.y), f.z)
+    }
+}
+
+pub struct ColorGrading {
+    pub lut: Lut3d,
+    pub strength: f32,
+}
+
+impl ColorGrading {
+    pub fn new(lut: Lut3d) -> Self {
+        Self { lut, strength: 1.0 }
+    }
+}
+
+impl PostEffect for ColorGrading {
+    fn name(&self) -> &'static str {
+        "Color grading"
+    }
+
+    fn apply(&mut self, target: &mut Framebuffer, _camera: &Camera) {
+        for color in target.color.iter_mut() {
+            let c = unpack_pixel(*color);
+            *color = pack(c.lerp(self.lut.sample(c), self.strength));
+        }
+    }
+}
+
+// Unsharp mask with a cross shaped kernel
+pub struct Sharpen {
+    pub amount: f32,
+}
+
+impl Default for Sharpen {
+    fn default() -> Self {
+        Self { amount: 0.5 }
+    }
+}
+
+impl PostEffect for Sharpen {
+    fn name(&self) -> &'static str {
+        "Sharpen"
+    }
+
+    fn apply(&mut self, target: &mut Framebuffer, _camera: &Camera) {
+        let (width, height) = (target.width, target.height);
+        let image = unpack(&target.color);
+        for y in 0..height as i32 {
+            for x in 0..width as i32 {
+                let c = fetch(&image, width, height, x, y);
+                let neighbours = fetch(&image, width, height, x - 1, y)
+                    + fetch(&image, width, height, x + 1, y)
+                    + fetch(&image, width, height, x, y - 1)
+                    + fetch(&image, width, height, x, y + 1);
+                target.color[x as usize + y as usize * width] = pack(c + (c * 4.0 - neighbours) * self.amount);
+            }
+        }
+    }
+}
+
+// Red and blue channels are shifted radially, `strength` is the shift in pixels at the corners
+pub struct ChromaticAberration {
+    pub strength: f32,
+}
+
+impl Default for ChromaticAberration {
+    fn default() -> Self {
+        Self { strength: 3.0 }
+    }
+}
+
+impl PostEffect for ChromaticAberration {
+    fn name(&self) -> &'static str {
+        "Chromatic aberration"
+    }
+
+    fn apply(&mut self, target: &mut Framebuffer, _camera: &Camera) {
+        let (width, height) = (target.width, target.height);
+        let image = unpack(&target.color);
+        let half = target.size() * 0.5;
+        for y in 0..height {
+            for x in 0..width {
+                let p = glam::vec2(x as f32 + 0.5, y as f32 + 0.5);
+                let offset = (p - half) / half.length() * self.strength;
+                let r = sample_bilinear(&image, width, height, p + offset).x;
+                let g = image[x + y * width].y;
+                let b = sample_bilinear(&image, width, height, p - offset).z;
+                target.color[x + y * width] = pack(glam::vec3(r, g, b));
+            }
+        }
+    }
+}
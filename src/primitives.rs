@@ -0,0 +1,293 @@
+use glam::{UVec2, UVec3, Vec2, Vec3};
+use std::collections::HashMap;
+use std::f32::consts::{PI, TAU};
+
+use crate::geometry::Mesh;
+
+// Generators of simple shapes centered at the origin, with Y up.
+// Triangles are counter-clockwise seen from the outside and texture coordinates
+// have v pointing down, like the ones loaded from glTF.
+
+// Point of a profile that is revolved around the Y axis
+#[derive(Debug, Copy, Clone)]
+struct ProfilePoint {
+    radius: f32,
+    y: f32,
+    normal: Vec2, // (outwards, up)
+    v: f32,
+}
+
+#[derive(Default)]
+struct Builder {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<Vec2>,
+    triangles: Vec<UVec3>,
+}
+
+impl Builder {
+    fn vertex(&mut self, position: Vec3, normal: Vec3, uv: Vec2) -> u32 {
+        self.positions.push(position);
+        self.normals.push(normal);
+        self.uvs.push(uv);
+        self.positions.len() as u32 - 1
+    }
+
+    // triangles collapsed to a line or a point, like the ones at the poles, are left out
+    fn triangle(&mut self, a: u32, b: u32, c: u32) {
+        let [pa, pb, pc] = [a, b, c].map(|i| self.positions[i as usize]);
+        let longest = (pb - pa).length_squared().max((pc - pb).length_squared()).max((pa - pc).length_squared());
+        if (pb - pa).cross(pc - pa).length_squared() > longest * longest * 1e-10 {
+            self.triangles.push(UVec3::new(a, b, c));
+        }
+    }
+
+    // `(columns + 1) * (rows + 1)` vertices from `vertex(u, v)`, u going right and v going down
+    // when looking at the front of the surface
+    fn surface(&mut self, columns: u32, rows: u32, vertex: impl Fn(f32, f32) -> (Vec3, Vec3, Vec2)) {
+        let first = self.positions.len() as u32;
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let (position, normal, uv) = vertex(column as f32 / columns as f32, row as f32 / rows as f32);
+                self.vertex(position, normal, uv);
+            }
+        }
+        let index = |column: u32, row: u32| first + row * (columns + 1) + column;
+        for row in 0..rows {
+            for column in 0..columns {
+                let (top_left, top_right) = (index(column, row), index(column + 1, row));
+                let (bottom_left, bottom_right) = (index(column, row + 1), index(column + 1, row + 1));
+                self.triangle(top_left, bottom_left, bottom_right);
+                self.triangle(top_left, bottom_right, top_right);
+            }
+        }
+    }
+
+    // the profile goes from the top to the bottom of the outer side
+    fn revolve(&mut self, profile: &[ProfilePoint], segments: u32, planar_uvs: bool) {
+        let rows = profile.len() as u32 - 1;
+        let max_radius = profile.iter().fold(0.0f32, |max, p| max.max(p.radius));
+        self.surface(segments, rows, |u, v| {
+            let point = profile[(v * rows as f32).round() as usize];
+            let (sin, cos) = (u * TAU).sin_cos();
+            let position = glam::vec3(point.radius * sin, point.y, point.radius * cos);
+            let normal = glam::vec3(point.normal.x * sin, point.normal.y, point.normal.x * cos);
+            let uv = if planar_uvs {
+                glam::vec2(position.x, position.z) / (2.0 * max_radius) + 0.5
+            } else {
+                glam::vec2(u, point.v)
+            };
+            (position, normal, uv)
+        });
+    }
+
+    // flat disk at `y`, facing up or down
+    fn cap(&mut self, radius: f32, y: f32, up: bool, segments: u32) {
+        let normal = glam::vec2(0.0, if up { 1.0 } else { -1.0 });
+        let center = ProfilePoint { radius: 0.0, y, normal, v: 0.0 };
+        let rim = ProfilePoint { radius, y, normal, v: 1.0 };
+        let profile = if up { [center, rim] } else { [rim, center] };
+        self.revolve(&profile, segments, true);
+    }
+
+    fn finish(self) -> Mesh {
+        let mut mesh = Mesh::new();
+        mesh.add_section_from_buffers(&self.triangles, &self.positions, &self.normals, &[], &self.uvs);
+        mesh
+    }
+}
+
+// points of a circular arc of the profile, `from` and `to` are angles from the top
+fn arc(radius: f32, center: Vec2, from: f32, to: f32, steps: u32) -> Vec<ProfilePoint> {
+    (0..=steps)
+        .map(|i| {
+            let angle = from + (to - from) * i as f32 / steps as f32;
+            let normal = glam::vec2(angle.sin(), angle.cos());
+            ProfilePoint {
+                radius: center.x + radius * normal.x,
+                y: center.y + radius * normal.y,
+                normal,
+                v: 0.0,
+            }
+        })
+        .collect()
+}
+
+// texture coordinates follow the length of the profile
+fn with_arc_length_v(mut profile: Vec<ProfilePoint>) -> Vec<ProfilePoint> {
+    let lengths: Vec<f32> = std::iter::once(0.0)
+        .chain(profile.windows(2).scan(0.0, |total, pair| {
+            *total += glam::vec2(pair[1].radius - pair[0].radius, pair[1].y - pair[0].y).length();
+            Some(*total)
+        }))
+        .collect();
+    let total = lengths.last().copied().unwrap_or(0.0).max(f32::EPSILON);
+    for (point, length) in profile.iter_mut().zip(lengths) {
+        point.v = length / total;
+    }
+    profile
+}
+
+// every face is split into `segments * segments` quads
+pub fn cube(size: f32, segments: u32) -> Mesh {
+    let segments = segments.max(1);
+    // normal, right and down directions of each face
+    let faces = [
+        (Vec3::Z, Vec3::X, Vec3::NEG_Y),
+        (Vec3::NEG_Z, Vec3::NEG_X, Vec3::NEG_Y),
+        (Vec3::X, Vec3::NEG_Z, Vec3::NEG_Y),
+        (Vec3::NEG_X, Vec3::Z, Vec3::NEG_Y),
+        (Vec3::Y, Vec3::X, Vec3::Z),
+        (Vec3::NEG_Y, Vec3::X, Vec3::NEG_Z),
+    ];
+    let mut builder = Builder::default();
+    for (normal, right, down) in faces {
+        builder.surface(segments, segments, |u, v| {
+            let position = (normal * 0.5 + right * (u - 0.5) + down * (v - 0.5)) * size;
+            (position, normal, glam::vec2(u, v))
+        });
+    }
+    builder.finish()
+}
+
+// grid in the XZ plane facing up
+pub fn plane(size: Vec2, segments: UVec2) -> Mesh {
+    let segments = segments.max(UVec2::ONE);
+    let mut builder = Builder::default();
+    builder.surface(segments.x, segments.y, |u, v| {
+        let position = glam::vec3((u - 0.5) * size.x, 0.0, (v - 0.5) * size.y);
+        (position, Vec3::Y, glam::vec2(u, v))
+    });
+    builder.finish()
+}
+
+// `segments` around the Y axis and `rings` from pole to pole
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Mesh {
+    let (segments, rings) = (segments.max(3), rings.max(2));
+    let profile = with_arc_length_v(arc(radius, Vec2::ZERO, 0.0, PI, rings));
+    let mut builder = Builder::default();
+    builder.revolve(&profile, segments, false);
+    builder.finish()
+}
+
+// every subdivision splits each triangle of an icosahedron into four
+pub fn icosphere(radius: f32, subdivisions: u32) -> Mesh {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut directions: Vec<Vec3> = [
+        (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+        (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+        (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+    ]
+    .iter()
+    .map(|(x, y, z)| glam::vec3(*x, *y, *z).normalize())
+    .collect();
+    let mut faces: Vec<[u32; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut middles: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut middle = |a: u32, b: u32| {
+            *middles.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                directions.push((directions[a as usize] + directions[b as usize]).normalize());
+                directions.len() as u32 - 1
+            })
+        };
+        faces = faces
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let (ab, bc, ca) = (middle(a, b), middle(b, c), middle(c, a));
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    // same mapping as the UV sphere, u = 0 is at +Z
+    let uv_of = |d: Vec3| glam::vec2((d.x.atan2(d.z) / TAU).rem_euclid(1.0), d.y.clamp(-1.0, 1.0).acos() / PI);
+    let mut builder = Builder::default();
+    // vertices are shared unless the seam or a pole gives them different texture coordinates
+    let mut vertices: HashMap<(u32, [u32; 2]), u32> = HashMap::new();
+    for face in faces {
+        let mut uvs = face.map(|i| uv_of(directions[i as usize]));
+        // triangles crossing the seam get their own vertices on the far side of it
+        let (min_u, max_u) = uvs.iter().fold((1.0f32, 0.0f32), |(min, max), uv| (min.min(uv.x), max.max(uv.x)));
+        if max_u - min_u > 0.5 {
+            for uv in uvs.iter_mut().filter(|uv| uv.x < 0.5) {
+                uv.x += 1.0;
+            }
+        }
+        // the u of a pole is undefined, the middle of the other two avoids a twisted texture
+        for k in 0..3 {
+            if directions[face[k] as usize].y.abs() > 1.0 - 1e-6 {
+                uvs[k].x = (uvs[(k + 1) % 3].x + uvs[(k + 2) % 3].x) / 2.0;
+            }
+        }
+        let [a, b, c] = [0, 1, 2].map(|k| {
+            let direction = directions[face[k] as usize];
+            *vertices
+                .entry((face[k], uvs[k].to_array().map(f32::to_bits)))
+                .or_insert_with(|| builder.vertex(direction * radius, direction, uvs[k]))
+        });
+        builder.triangle(a, b, c);
+    }
+    builder.finish()
+}
+
+// `rings` splits the side along its height, the caps are separate flat disks
+pub fn cylinder(radius: f32, height: f32, segments: u32, rings: u32) -> Mesh {
+    let (segments, rings) = (segments.max(3), rings.max(1));
+    let profile: Vec<ProfilePoint> = (0..=rings)
+        .map(|i| {
+            let v = i as f32 / rings as f32;
+            ProfilePoint { radius, y: height * (0.5 - v), normal: Vec2::X, v }
+        })
+        .collect();
+    let mut builder = Builder::default();
+    builder.revolve(&profile, segments, false);
+    builder.cap(radius, height / 2.0, true, segments);
+    builder.cap(radius, -height / 2.0, false, segments);
+    builder.finish()
+}
+
+// apex at the top, base at the bottom
+pub fn cone(radius: f32, height: f32, segments: u32, rings: u32) -> Mesh {
+    let (segments, rings) = (segments.max(3), rings.max(1));
+    let normal = glam::vec2(height, radius).normalize_or_zero();
+    let profile: Vec<ProfilePoint> = (0..=rings)
+        .map(|i| {
+            let v = i as f32 / rings as f32;
+            ProfilePoint { radius: radius * v, y: height * (0.5 - v), normal, v }
+        })
+        .collect();
+    let mut builder = Builder::default();
+    builder.revolve(&profile, segments, false);
+    builder.cap(radius, -height / 2.0, false, segments);
+    builder.finish()
+}
+
+// ring around the Y axis, `segments` around it and `sides` around the tube
+pub fn torus(radius: f32, tube_radius: f32, segments: u32, sides: u32) -> Mesh {
+    let (segments, sides) = (segments.max(3), sides.max(3));
+    // the tube starts at its top, goes outside and comes back through the hole
+    let mut profile = arc(tube_radius, glam::vec2(radius, 0.0), 0.0, TAU, sides);
+    for (i, point) in profile.iter_mut().enumerate() {
+        point.v = i as f32 / sides as f32;
+    }
+    let mut builder = Builder::default();
+    builder.revolve(&profile, segments, false);
+    builder.finish()
+}
+
+// cylinder of `height` with half spheres on both ends, `rings` for each half sphere
+pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32) -> Mesh {
+    let (segments, rings) = (segments.max(3), rings.max(1));
+    let half = height / 2.0;
+    let mut profile = arc(radius, glam::vec2(0.0, half), 0.0, PI / 2.0, rings);
+    profile.extend(arc(radius, glam::vec2(0.0, -half), PI / 2.0, PI, rings));
+    let mut builder = Builder::default();
+    builder.revolve(&with_arc_length_v(profile), segments, false);
+    builder.finish()
+}
@@ -0,0 +1,383 @@
+use glam::{DVec3, IVec3, UVec3};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::geometry::Vertex;
+use crate::normals::face_normal;
+
+// Index buffer and vertex operations behind the processing methods of `Mesh`.
+// They work on plain slices, keeping groups, lines and points up to date is left to the mesh.
+
+// the normal, color and texture coordinates of welded vertices may differ by this much
+const ATTRIBUTE_TOLERANCE: f32 = 1e-4;
+// vertices split by a seam are this close to each other
+const SEAM_TOLERANCE: f32 = 1e-6;
+
+// For every vertex the index of the first vertex it can be merged with, or itself.
+// Positions have to be within `tolerance`, the other attributes have to be (almost) equal
+// when `compare_attributes` is set.
+pub fn weld_map(vertices: &[Vertex], tolerance: f32, compare_attributes: bool) -> Vec<u32> {
+    let cell_size = tolerance.max(f32::EPSILON);
+    let cell = |v: &Vertex| (v.pos / cell_size).floor().as_ivec3();
+    let same_attributes = |a: &Vertex, b: &Vertex| {
+        !compare_attributes
+            || (a.normal.abs_diff_eq(b.normal, ATTRIBUTE_TOLERANCE)
+                && a.uv.abs_diff_eq(b.uv, ATTRIBUTE_TOLERANCE)
+                && a.c.abs_diff_eq(b.c, ATTRIBUTE_TOLERANCE))
+    };
+
+    // the vertices kept so far, sorted into cells as big as the tolerance
+    let mut grid: HashMap<IVec3, Vec<u32>> = HashMap::new();
+    let mut map = Vec::with_capacity(vertices.len());
+    for (i, vertex) in vertices.iter().enumerate() {
+        let center = cell(vertex);
+        let mut found = None;
+        'search: for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let Some(candidates) = grid.get(&(center + IVec3::new(x, y, z))) else {
+                        continue;
+                    };
+                    found = candidates.iter().copied().find(|c| {
+                        let other = &vertices[*c as usize];
+                        other.pos.distance(vertex.pos) <= tolerance && same_attributes(vertex, other)
+                    });
+                    if found.is_some() {
+                        break 'search;
+                    }
+                }
+            }
+        }
+        map.push(found.unwrap_or_else(|| {
+            grid.entry(center).or_default().push(i as u32);
+            i as u32
+        }));
+    }
+    map
+}
+
+// triangles using a vertex twice or without any area
+pub fn is_degenerate(triangle: UVec3, vertices: &[Vertex]) -> bool {
+    let [a, b, c] = triangle.to_array();
+    if a == b || b == c || c == a {
+        return true;
+    }
+    let [a, b, c] = [a, b, c].map(|i| vertices[i as usize].pos);
+    face_normal(a, b, c) == glam::Vec3::ZERO
+}
+
+// Linear-speed vertex cache optimisation by Tom Forsyth
+const CACHE_SIZE: usize = 32;
+
+fn vertex_score(cache_position: Option<usize>, remaining: u32) -> f32 {
+    if remaining == 0 {
+        return -1.0;
+    }
+    let cache = match cache_position {
+        None => 0.0,
+        // the last triangle is scored lower, so strips do not just turn around
+        Some(position) if position < 3 => 0.75,
+        Some(position) => (1.0 - (position - 3) as f32 / (CACHE_SIZE - 3) as f32).powf(1.5),
+    };
+    // vertices with few triangles left are finished first
+    cache + 2.0 * (remaining as f32).powf(-0.5)
+}
+
+// reorders the triangles so they reuse the vertices still in the post-transform cache
+pub fn optimize_vertex_cache(triangles: &mut [UVec3]) {
+    // local indices of the vertices used by `triangles`
+    let mut local: HashMap<u32, usize> = HashMap::new();
+    let corners: Vec<[usize; 3]> = triangles
+        .iter()
+        .map(|t| {
+            t.to_array().map(|i| {
+                let count = local.len();
+                *local.entry(i).or_insert(count)
+            })
+        })
+        .collect();
+
+    let mut vertex_triangles: Vec<Vec<usize>> = vec![Vec::new(); local.len()];
+    for (t, triangle) in corners.iter().enumerate() {
+        for v in triangle {
+            vertex_triangles[*v].push(t);
+        }
+    }
+    let mut cache_position: Vec<Option<usize>> = vec![None; local.len()];
+    let mut scores: Vec<f32> = vertex_triangles.iter().map(|t| vertex_score(None, t.len() as u32)).collect();
+    let mut triangle_scores: Vec<f32> = corners.iter().map(|t| t.iter().map(|v| scores[*v]).sum()).collect();
+    let mut added = vec![false; corners.len()];
+
+    let mut order = Vec::with_capacity(corners.len());
+    let mut cache: Vec<usize> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut best = None;
+    let mut first_left = 0;
+    while order.len() < corners.len() {
+        // nothing in the cache is useful any more, continue with the first triangle left
+        let next = best.unwrap_or_else(|| {
+            while added[first_left] {
+                first_left += 1;
+            }
+            first_left
+        });
+        added[next] = true;
+        order.push(next);
+
+        for v in corners[next] {
+            vertex_triangles[v].retain(|t| *t != next);
+        }
+        let evicted = cache.clone();
+        cache.retain(|v| !corners[next].contains(v));
+        cache.splice(0..0, corners[next]);
+        cache.truncate(CACHE_SIZE + 3);
+
+        for v in evicted.iter().chain(&corners[next]) {
+            cache_position[*v] = None;
+        }
+        for (position, v) in cache.iter().enumerate() {
+            cache_position[*v] = (position < CACHE_SIZE).then_some(position);
+        }
+
+        best = None;
+        let mut best_score = -1.0;
+        for v in evicted.iter().chain(&corners[next]) {
+            let score = vertex_score(cache_position[*v], vertex_triangles[*v].len() as u32);
+            let delta = score - scores[*v];
+            scores[*v] = score;
+            for t in &vertex_triangles[*v] {
+                triangle_scores[*t] += delta;
+            }
+        }
+        for v in &cache {
+            for t in &vertex_triangles[*v] {
+                if triangle_scores[*t] > best_score {
+                    best_score = triangle_scores[*t];
+                    best = Some(*t);
+                }
+            }
+        }
+    }
+
+    let original = triangles.to_vec();
+    for (slot, t) in order.into_iter().enumerate() {
+        triangles[slot] = original[t];
+    }
+}
+
+// Symmetric 4x4 matrix measuring the squared distance to a set of planes, by Garland and Heckbert
+#[derive(Debug, Copy, Clone, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(normal: DVec3, point: DVec3, weight: f64) -> Self {
+        let (a, b, c) = (normal.x, normal.y, normal.z);
+        let d = -normal.dot(point);
+        Self([a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d].map(|x| x * weight))
+    }
+
+    fn add(&mut self, other: &Self) {
+        for (a, b) in self.0.iter_mut().zip(other.0) {
+            *a += b;
+        }
+    }
+
+    fn error(&self, p: DVec3) -> f64 {
+        let [aa, ab, ac, ad, bb, bc, bd, cc, cd, dd] = self.0;
+        aa * p.x * p.x + 2.0 * ab * p.x * p.y + 2.0 * ac * p.x * p.z + 2.0 * ad * p.x
+            + bb * p.y * p.y + 2.0 * bc * p.y * p.z + 2.0 * bd * p.y
+            + cc * p.z * p.z + 2.0 * cd * p.z
+            + dd
+    }
+}
+
+// a collapse may turn the remaining triangles by at most ~75°
+const MIN_NORMAL_COS: f64 = 0.25;
+
+// collapse of the vertex `from` into `to`, valid as long as both are unchanged
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Collapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+    versions: (u32, u32),
+}
+
+impl Eq for Collapse {}
+
+impl Ord for Collapse {
+    // the heap pops the cheapest collapse first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Collapses edges in order of their quadric error until at most `target` triangles are left.
+// Vertices are moved onto one of their neighbours, so no attributes have to be interpolated.
+// Vertices at the same position, split by texture seams or other attributes, are one vertex
+// of the surface: they collapse together, each onto its own copy of the neighbour, and only
+// along the seam. Vertices on the borders of the surface are never removed, and neither are
+// the ones whose copies have no neighbour to go to, so unwelded meshes stay above `target`.
+// Returns which triangles are still alive, their indices are updated in place.
+pub fn simplify(triangles: &mut [UVec3], vertices: &[Vertex], target: usize) -> Vec<bool> {
+    let position = |v: u32| vertices[v as usize].pos.as_dvec3();
+    // the first vertex at the position of every vertex, and the vertices at every such position
+    let surface = weld_map(vertices, SEAM_TOLERANCE, false);
+    let point = |v: u32| surface[v as usize] as usize;
+    let mut copies: Vec<Vec<u32>> = vec![Vec::new(); vertices.len()];
+    for v in 0..vertices.len() as u32 {
+        copies[point(v)].push(v);
+    }
+
+    // quadrics and borders belong to the surface, edges to the vertices
+    let mut quadrics = vec![Quadric::default(); vertices.len()];
+    let mut vertex_triangles: Vec<Vec<usize>> = vec![Vec::new(); vertices.len()];
+    let mut edge_uses: HashMap<(usize, usize), u32> = HashMap::new();
+    let mut edges: HashSet<(u32, u32)> = HashSet::new();
+    for (t, triangle) in triangles.iter().enumerate() {
+        let [a, b, c] = triangle.to_array();
+        let cross = (position(b) - position(a)).cross(position(c) - position(a));
+        // weighted by the area, so small triangles do not dominate
+        let quadric = Quadric::from_plane(cross.normalize_or_zero(), position(a), cross.length() / 2.0);
+        for (v, w) in [(a, b), (b, c), (c, a)] {
+            quadrics[point(v)].add(&quadric);
+            vertex_triangles[v as usize].push(t);
+            *edge_uses.entry((point(v).min(point(w)), point(v).max(point(w)))).or_default() += 1;
+            edges.insert((v.min(w), v.max(w)));
+        }
+    }
+    let mut border = vec![false; vertices.len()];
+    for ((v, w), uses) in &edge_uses {
+        if *uses == 1 {
+            border[*v] = true;
+            border[*w] = true;
+        }
+    }
+    let locked: Vec<bool> = (0..vertices.len() as u32).map(|v| border[point(v)]).collect();
+
+    let mut versions = vec![0u32; vertices.len()];
+    let mut removed = vec![false; vertices.len()];
+    let mut alive = vec![true; triangles.len()];
+    let mut live = triangles.len();
+
+    let mut heap = BinaryHeap::new();
+    let candidate = |from: u32, to: u32, quadrics: &[Quadric], versions: &[u32]| {
+        let mut quadric = quadrics[point(from)];
+        quadric.add(&quadrics[point(to)]);
+        Collapse {
+            cost: quadric.error(position(to)),
+            from,
+            to,
+            versions: (versions[from as usize], versions[to as usize]),
+        }
+    };
+    for (v, w) in &edges {
+        for (from, to) in [(*v, *w), (*w, *v)] {
+            if !locked[from as usize] {
+                heap.push(candidate(from, to, &quadrics, &versions));
+            }
+        }
+    }
+
+    while live > target {
+        let Some(collapse) = heap.pop() else {
+            break;
+        };
+        let (from, to) = (collapse.from as usize, collapse.to as usize);
+        if removed[from] || removed[to] || collapse.versions != (versions[from], versions[to]) {
+            continue;
+        }
+        // copies of one vertex only share degenerate triangles
+        if point(collapse.from) == point(collapse.to) {
+            continue;
+        }
+
+        // every copy of `from` still in use goes onto the copy of `to` it shares a triangle with
+        let mut moves = Vec::new();
+        for copy in &copies[point(collapse.from)] {
+            let triangles_of_copy = vertex_triangles[*copy as usize].iter().filter(|t| alive[**t]);
+            let target = if *copy == collapse.from {
+                Some(collapse.to)
+            } else {
+                triangles_of_copy
+                    .clone()
+                    .flat_map(|t| triangles[*t].to_array())
+                    .find(|v| *v != *copy && point(*v) == point(collapse.to))
+            };
+            match target {
+                Some(target) => moves.push((*copy, target)),
+                None if triangles_of_copy.count() == 0 => {}
+                // the copy is not on the edge, moving it alone would tear the surface
+                None => break,
+            }
+        }
+        let in_use = copies[point(collapse.from)]
+            .iter()
+            .filter(|copy| vertex_triangles[**copy as usize].iter().any(|t| alive[*t]))
+            .count();
+        if moves.len() < in_use {
+            continue;
+        }
+
+        // moving the vertex must not turn any of the remaining triangles over
+        let flips = moves.iter().any(|(from, to)| {
+            vertex_triangles[*from as usize].iter().filter(|t| alive[**t]).any(|t| {
+                let triangle = triangles[*t];
+                if triangle.to_array().contains(to) {
+                    return false;
+                }
+                let [a, b, c] = triangle.to_array().map(position);
+                let moved = triangle.to_array().map(|v| if v == *from { position(*to) } else { position(v) });
+                let before = (b - a).cross(c - a).normalize_or_zero();
+                let after = (moved[1] - moved[0]).cross(moved[2] - moved[0]).normalize_or_zero();
+                before.dot(after) < MIN_NORMAL_COS
+            })
+        });
+        if flips {
+            continue;
+        }
+
+        let quadric = quadrics[point(collapse.from)];
+        quadrics[point(collapse.to)].add(&quadric);
+        for (from, to) in moves {
+            let (from_index, to_index) = (from as usize, to as usize);
+            for t in std::mem::take(&mut vertex_triangles[from_index]) {
+                if !alive[t] {
+                    continue;
+                }
+                if triangles[t].to_array().contains(&to) {
+                    alive[t] = false;
+                    live -= 1;
+                } else {
+                    triangles[t] = UVec3::from_array(triangles[t].to_array().map(|v| if v == from { to } else { v }));
+                    vertex_triangles[to_index].push(t);
+                }
+            }
+            removed[from_index] = true;
+            versions[to_index] += 1;
+
+            vertex_triangles[to_index].retain(|t| alive[*t]);
+            vertex_triangles[to_index].sort_unstable();
+            vertex_triangles[to_index].dedup();
+            for t in vertex_triangles[to_index].clone() {
+                for neighbour in triangles[t].to_array() {
+                    if neighbour == to {
+                        continue;
+                    }
+                    if !locked[neighbour as usize] {
+                        heap.push(candidate(neighbour, to, &quadrics, &versions));
+                    }
+                    if !locked[to_index] {
+                        heap.push(candidate(to, neighbour, &quadrics, &versions));
+                    }
+                }
+            }
+        }
+    }
+    alive
+}
@@ -0,0 +1,123 @@
+use glam::{Vec3, Vec4};
+
+use crate::draw_state::DrawState;
+use crate::framebuffer::Framebuffer;
+use crate::transparency::{self, BlendMode, Fragment};
+use crate::utils::{alpha_of_argb8, coords_to_index, from_argb8_v};
+
+// signed distance to the near plane in clip space, negative behind it
+pub fn near_plane_distance(clip: Vec4, reverse_z: bool) -> f32 {
+    if reverse_z { clip.w - clip.z } else { clip.z }
+}
+
+// With deferred shading, blended fragments wait in the A-buffer for the lit surfaces below them
+pub fn uses_a_buffer(state: &DrawState, target: &Framebuffer) -> bool {
+    state.blend == BlendMode::ABuffer || (state.blend == BlendMode::Alpha && target.g_buffer.is_some())
+}
+
+// writes an already depth tested color into a pixel or a sample
+pub fn write_color(state: &DrawState, color: &mut u32, depth: &mut f32, z: f32, argb: u32) {
+    if state.depth.write {
+        *depth = z;
+    }
+    match state.blend {
+        BlendMode::Opaque => {
+            *color = argb;
+        }
+        BlendMode::Alpha | BlendMode::ABuffer => {
+            let alpha = alpha_of_argb8(argb) * state.opacity;
+            *color = transparency::blend_over(from_argb8_v(argb), alpha, *color);
+        }
+    }
+}
+
+// linear color of the HDR buffer, blended the same way as `write_color`
+pub fn write_hdr(state: &DrawState, hdr: &mut Vec3, argb: u32) {
+    let color = from_argb8_v(argb) / 255.0 * state.intensity;
+    match state.blend {
+        BlendMode::Opaque => *hdr = color,
+        BlendMode::Alpha | BlendMode::ABuffer => *hdr = hdr.lerp(color, alpha_of_argb8(argb) * state.opacity),
+    }
+}
+
+// Depth and stencil tested write of a fragment covering the whole pixel (every sample with MSAA),
+// used by the line and point rasterizers. `coverage` below 1.0 blends the color like alpha does.
+pub fn draw_fragment(
+    target: &mut Framebuffer,
+    state: &DrawState,
+    x: usize, y: usize,
+    z: f32,
+    coverage: f32,
+    shade: impl FnOnce() -> u32)
+{
+    if x >= target.width || y >= target.height || coverage <= 0.0 { return; }
+
+    let index = coords_to_index(x, y, target.width);
+    let samples = target.samples;
+
+    let mut passed: u32 = 0;
+    if samples > 1 {
+        for s in 0..samples {
+            let sample = index * samples + s;
+            if state.depth_stencil_test(&mut target.sample_stencil[sample], z, target.sample_depth[sample]) {
+                passed |= 1 << s;
+            }
+        }
+    } else if state.depth_stencil_test(&mut target.stencil[index], z, target.depth[index]) {
+        passed = 1;
+    }
+    if passed == 0 { return; }
+    if state.depth.write {
+        if let Some(hiz) = target.hiz.as_mut() { hiz.widen(x, y, (z, z)); }
+    }
+
+    // partially covered pixels are blended and do not hide what is behind them
+    let mut state = *state;
+    if coverage < 1.0 {
+        state.opacity *= coverage;
+        if state.blend == BlendMode::Opaque { state.blend = BlendMode::Alpha; }
+        state.depth.write &= coverage >= 0.5;
+    }
+
+    if !state.color_write {
+        if state.depth.write {
+            if samples > 1 {
+                for s in (0..samples).filter(|s| passed & (1 << s) != 0) { target.sample_depth[index * samples + s] = z; }
+            } else {
+                target.depth[index] = z;
+            }
+        }
+        return;
+    }
+
+    if let Some(debug_view) = target.debug_view.as_mut() {
+        debug_view.count(index);
+    }
+    let color = shade();
+
+    if uses_a_buffer(&state, target) {
+        if let Some(a_buffer) = target.a_buffer.as_mut() {
+            a_buffer.append(index, Fragment {
+                color: from_argb8_v(color),
+                alpha: alpha_of_argb8(color) * state.opacity,
+                depth: z,
+            });
+            return;
+        }
+    }
+
+    if samples > 1 {
+        for s in (0..samples).filter(|s| passed & (1 << s) != 0) {
+            let sample = index * samples + s;
+            write_color(&state, &mut target.sample_color[sample], &mut target.sample_depth[sample], z, color);
+        }
+    } else {
+        write_color(&state, &mut target.color[index], &mut target.depth[index], z, color);
+    }
+    if !target.hdr.is_empty() {
+        write_hdr(&state, &mut target.hdr[index], color);
+    }
+    if state.blend == BlendMode::Opaque {
+        if let Some(g_buffer) = target.g_buffer.as_mut() { g_buffer.discard(index); }
+    }
+}
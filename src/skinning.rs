@@ -0,0 +1,180 @@
+use glam::{Mat4, Quat, UVec4, Vec3, Vec4};
+
+use crate::geometry::Vertex;
+use crate::transform::Transform;
+
+// Up to four joints influencing a vertex, weights of zero mean the vertex is not skinned
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct JointWeights {
+    pub joints: UVec4,
+    pub weights: Vec4,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SkinningMethod {
+    LinearBlend,
+    // keeps the volume around twisting joints, scale in the joint matrices is ignored
+    DualQuaternion,
+}
+
+// Hierarchy of the nodes of a glTF document with their local transforms, animations write into them
+#[derive(Debug, Clone)]
+pub struct NodeTree {
+    pub parents: Vec<Option<usize>>,
+    pub transforms: Vec<Transform>,
+    // transforms of the document, before any animation
+    pub rest: Vec<Transform>,
+    // morph target weights of the nodes with a mesh, empty when the mesh has no targets
+    pub weights: Vec<Vec<f32>>,
+    pub rest_weights: Vec<Vec<f32>>,
+}
+
+impl NodeTree {
+    pub fn from_gltf(document: &gltf::Document) -> Self {
+        let count = document.nodes().count();
+        let mut parents = vec![None; count];
+        for node in document.nodes() {
+            for child in node.children() {
+                parents[child.index()] = Some(node.index());
+            }
+        }
+        let transforms: Vec<Transform> = document
+            .nodes()
+            .map(|node| {
+                let (translation, rotation, scale) = node.transform().decomposed();
+                Transform::new(Vec3::from(translation), Quat::from_array(rotation), Vec3::from(scale))
+            })
+            .collect();
+        // the weights of the node replace the ones of its mesh
+        let weights: Vec<Vec<f32>> = document
+            .nodes()
+            .map(|node| {
+                let weights = node.weights().or_else(|| node.mesh().and_then(|mesh| mesh.weights()));
+                weights.map(<[f32]>::to_vec).unwrap_or_default()
+            })
+            .collect();
+        Self {
+            parents,
+            rest: transforms.clone(),
+            transforms,
+            rest_weights: weights.clone(),
+            weights,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.transforms.clone_from(&self.rest);
+        self.weights.clone_from(&self.rest_weights);
+    }
+
+    // transform of `node` in the space of the scene
+    pub fn global(&self, node: usize) -> Mat4 {
+        self.global_of(node, &self.transforms)
+    }
+
+    pub fn rest_global(&self, node: usize) -> Mat4 {
+        self.global_of(node, &self.rest)
+    }
+
+    fn global_of(&self, node: usize, transforms: &[Transform]) -> Mat4 {
+        let mut matrix = transforms[node].local();
+        let mut parent = self.parents[node];
+        while let Some(index) = parent {
+            matrix = transforms[index].local() * matrix;
+            parent = self.parents[index];
+        }
+        matrix
+    }
+}
+
+// Joints of a skinned mesh, nodes of the tree with the matrices bringing the mesh into their space
+#[derive(Debug, Clone)]
+pub struct Skin {
+    pub joints: Vec<usize>,
+    pub inverse_bind_matrices: Vec<Mat4>,
+}
+
+impl Skin {
+    pub fn from_gltf(skin: &gltf::Skin, buffers: &[gltf::buffer::Data]) -> Self {
+        let joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+        let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+        // without the accessor every inverse bind matrix is the identity
+        let inverse_bind_matrices = match reader.read_inverse_bind_matrices() {
+            Some(matrices) => matrices.map(|m| Mat4::from_cols_array_2d(&m)).collect(),
+            None => vec![Mat4::IDENTITY; joints.len()],
+        };
+        Self { joints, inverse_bind_matrices }
+    }
+
+    // matrices moving the vertices from the bind pose into the current pose of `nodes`
+    pub fn joint_matrices(&self, nodes: &NodeTree) -> Vec<Mat4> {
+        self.joints
+            .iter()
+            .zip(&self.inverse_bind_matrices)
+            .map(|(joint, inverse_bind)| nodes.global(*joint) * *inverse_bind)
+            .collect()
+    }
+}
+
+// rotation and translation of a rigid transform as a unit dual quaternion
+#[derive(Debug, Copy, Clone)]
+struct DualQuat {
+    real: Quat,
+    dual: Quat,
+}
+
+impl DualQuat {
+    fn from_mat4(matrix: &Mat4) -> Self {
+        let (_, rotation, translation) = matrix.to_scale_rotation_translation();
+        let dual = Quat::from_xyzw(translation.x, translation.y, translation.z, 0.0) * rotation * 0.5;
+        Self { real: rotation, dual }
+    }
+
+    fn transform_point(&self, point: Vec3) -> Vec3 {
+        let translation = (self.dual * 2.0) * self.real.conjugate();
+        self.real * point + glam::vec3(translation.x, translation.y, translation.z)
+    }
+}
+
+// Vertex stage deformation, positions and normals are moved by the weighted joint matrices.
+// Vertices without weights stay where they are.
+pub fn skin_vertices(vertices: &[Vertex], weights: &[JointWeights], matrices: &[Mat4], method: SkinningMethod) -> Vec<Vertex> {
+    let matrix = |joint: u32| matrices.get(joint as usize).copied().unwrap_or(Mat4::IDENTITY);
+    vertices
+        .iter()
+        .zip(weights)
+        .map(|(vertex, influence)| {
+            let total = influence.weights.element_sum();
+            if total <= 0.0 {
+                return *vertex;
+            }
+            // weights are supposed to add up to one, exporters do not always make sure of it
+            let weights = influence.weights / total;
+            let joints = influence.joints.to_array();
+            let mut skinned = *vertex;
+            match method {
+                SkinningMethod::LinearBlend => {
+                    let blended = (0..4).fold(Mat4::ZERO, |sum, i| sum + matrix(joints[i]) * weights[i]);
+                    skinned.pos = blended.transform_point3(vertex.pos);
+                    skinned.normal = blended.transform_vector3(vertex.normal).normalize_or_zero();
+                }
+                SkinningMethod::DualQuaternion => {
+                    let first = DualQuat::from_mat4(&matrix(joints[0]));
+                    let (mut real, mut dual) = (Quat::from_xyzw(0.0, 0.0, 0.0, 0.0), Quat::from_xyzw(0.0, 0.0, 0.0, 0.0));
+                    for i in 0..4 {
+                        let dq = DualQuat::from_mat4(&matrix(joints[i]));
+                        // q and -q are the same rotation, blend along the shorter way
+                        let sign = if dq.real.dot(first.real) < 0.0 { -1.0 } else { 1.0 };
+                        real = real + dq.real * (weights[i] * sign);
+                        dual = dual + dq.dual * (weights[i] * sign);
+                    }
+                    let length = real.length();
+                    let blended = DualQuat { real: real / length, dual: dual / length };
+                    skinned.pos = blended.transform_point(vertex.pos);
+                    skinned.normal = (blended.real * vertex.normal).normalize_or_zero();
+                }
+            }
+            skinned
+        })
+        .collect()
+}
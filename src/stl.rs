@@ -0,0 +1,116 @@
+use glam::{UVec3, Vec2, Vec3};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::error::LoadError;
+use crate::geometry::{Mesh, Vertex};
+use crate::normals::NormalMode;
+use crate::utils::parse_floats;
+
+// 80 byte header followed by the triangle count
+const BINARY_HEADER_SIZE: usize = 84;
+// normal, 3 vertices and a 2 byte attribute
+const BINARY_TRIANGLE_SIZE: usize = 50;
+
+// Corners at the same position are merged and the normals generated with the default smoothing,
+// so curved surfaces are smooth and hard edges stay sharp
+pub fn load_stl(path: &Path) -> Result<Mesh, LoadError> {
+    let data = std::fs::read(path)?;
+    parse_stl(&data)
+}
+
+// Binary files may start with "solid" too. A file of exactly the size of its triangle count is
+// binary, then one reading as "solid ... facet" is ASCII. Any other file big enough for its
+// triangles is binary with bytes after them, which some exporters pad or append.
+pub fn parse_stl(data: &[u8]) -> Result<Mesh, LoadError> {
+    let binary_size = (data.len() >= BINARY_HEADER_SIZE).then(|| {
+        let count = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+        BINARY_HEADER_SIZE + count * BINARY_TRIANGLE_SIZE
+    });
+    let source = std::str::from_utf8(data).ok();
+    let is_ascii = source.is_some_and(|source| source.trim_start().starts_with("solid") && source.contains("facet"));
+
+    let facets = match (binary_size, source) {
+        (Some(size), _) if size == data.len() => parse_binary(&data[..size]),
+        (_, Some(source)) if is_ascii => parse_ascii(source)?,
+        (Some(size), _) if size < data.len() => parse_binary(&data[..size]),
+        (_, Some(source)) => parse_ascii(source)?,
+        _ => return Err(LoadError::Invalid("STL is neither ASCII nor binary".into())),
+    };
+    Ok(build_mesh(&facets))
+}
+
+// normal and the three corners
+type Facet = (Vec3, [Vec3; 3]);
+
+fn read_vec3(data: &[u8]) -> Vec3 {
+    let value = |i: usize| f32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+    glam::vec3(value(0), value(4), value(8))
+}
+
+fn parse_binary(data: &[u8]) -> Vec<Facet> {
+    data[BINARY_HEADER_SIZE..]
+        .chunks_exact(BINARY_TRIANGLE_SIZE)
+        .map(|chunk| {
+            (
+                read_vec3(&chunk[0..12]),
+                [read_vec3(&chunk[12..24]), read_vec3(&chunk[24..36]), read_vec3(&chunk[36..48])],
+            )
+        })
+        .collect()
+}
+
+fn parse_ascii(source: &str) -> Result<Vec<Facet>, LoadError> {
+    let mut facets = Vec::new();
+    let mut normal = Vec3::ZERO;
+    let mut corners: Vec<Vec3> = Vec::with_capacity(3);
+
+    for (number, text) in source.lines().enumerate() {
+        let line = number + 1;
+        let tokens: Vec<&str> = text.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["facet", "normal", args @ ..] => {
+                normal = Vec3::from(parse_floats::<3>(args, line)?);
+                corners.clear();
+            }
+            ["vertex", args @ ..] => corners.push(Vec3::from(parse_floats::<3>(args, line)?)),
+            ["endfacet", ..] => {
+                let [a, b, c] = corners[..] else {
+                    return Err(LoadError::parse(line, format!("facet with {} vertices", corners.len())));
+                };
+                facets.push((normal, [a, b, c]));
+            }
+            // solid, outer loop, endloop, endsolid
+            _ => {}
+        }
+    }
+    Ok(facets)
+}
+
+fn build_mesh(facets: &[Facet]) -> Mesh {
+    let mut vertices: Vec<Vertex> = Vec::with_capacity(facets.len() / 2);
+    let mut indices: HashMap<[u32; 3], u32> = HashMap::new();
+    let mut index_of = |position: Vec3| {
+        // -0.0 and 0.0 are the same position
+        let key = (position + Vec3::ZERO).to_array().map(f32::to_bits);
+        *indices.entry(key).or_insert_with(|| {
+            vertices.push(Vertex::new(position, Vec3::ZERO, Vec3::ONE, Vec2::ZERO));
+            vertices.len() as u32 - 1
+        })
+    };
+
+    let triangles: Vec<UVec3> = facets
+        .iter()
+        .map(|(normal, [a, b, c])| {
+            // the stored normal wins over the winding, many exporters leave it empty though
+            let flipped = (*b - *a).cross(*c - *a).dot(*normal) < 0.0;
+            let (b, c) = if flipped { (c, b) } else { (b, c) };
+            UVec3::new(index_of(*a), index_of(*b), index_of(*c))
+        })
+        .collect();
+
+    let mut mesh = Mesh::new();
+    mesh.add_section_from_vertices(&triangles, &vertices);
+    mesh.compute_normals(NormalMode::default());
+    mesh
+}
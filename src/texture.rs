@@ -11,17 +11,27 @@ pub struct Texture {
 
 impl Texture {
     pub fn load(path: &Path) -> Self {
-        let decoded_image = stb_image::image::load(path);
+        Self::from_decoded(stb_image::image::load(path))
+    }
+
+    // an encoded image, like the PNG or JPEG bytes embedded into a glTF file
+    pub fn from_memory(bytes: &[u8]) -> Self {
+        Self::from_decoded(stb_image::image::load_from_memory(bytes))
+    }
+
+    fn from_decoded(decoded_image: stb_image::image::LoadResult) -> Self {
         if let stb_image::image::LoadResult::ImageU8(image) = decoded_image {
-            // we are not taking into accoung pngs yet :)
-            let data = (0..image.data.len() / 3)
-                .map(|id| {
-                    to_argb8(
-                        255,
-                        image.data[id * 3],
-                        image.data[id * 3 + 1],
-                        image.data[id * 3 + 2],
-                    )
+            let channels = image.depth;
+            let data = image
+                .data
+                .chunks_exact(channels)
+                .map(|texel| match *texel {
+                    // grey, and grey with alpha
+                    [grey] => to_argb8(255, grey, grey, grey),
+                    [grey, alpha] => to_argb8(alpha, grey, grey, grey),
+                    [r, g, b] => to_argb8(255, r, g, b),
+                    [r, g, b, a, ..] => to_argb8(a, r, g, b),
+                    [] => unreachable!(),
                 })
                 .collect();
             Self {
@@ -35,6 +45,16 @@ impl Texture {
         }
     }
 
+    // 1x1 texture of a single color
+    pub fn from_color(argb: u32) -> Self {
+        Self {
+            width: 1,
+            height: 1,
+            data: vec![argb],
+            depth: 4,
+        }
+    }
+
     pub fn argb_at_uv(&self, u: f32, v: f32) -> u32 {
         let (u, v) = ((u % 1.0) * self.width as f32, (v % 1.0) * self.height as f32);
         let id = coords_to_index(u as usize, v as usize, self.width);
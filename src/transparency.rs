@@ -0,0 +1,178 @@
+use glam::Vec3;
+
+use crate::utils::{from_argb8_v, to_argb8_v};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlendMode {
+    // depth tested and depth written, alpha is ignored
+    Opaque,
+    // classic "over" blending in draw order, depth tested but not written
+    Alpha,
+    // fragments are stored per pixel and composited in depth order by `ABuffer::resolve`
+    ABuffer,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Fragment {
+    pub color: Vec3, // 0..255 per channel, same as `to_argb8_v`
+    pub alpha: f32,
+    pub depth: f32,
+}
+
+impl Fragment {
+    const EMPTY: Self = Self {
+        color: Vec3::ZERO,
+        alpha: 0.0,
+        depth: 0.0,
+    };
+
+    // `self` composited over `back`, the result keeps the depth of `self`
+    pub fn over(self, back: Fragment) -> Fragment {
+        let alpha = self.alpha + back.alpha * (1.0 - self.alpha);
+        if alpha <= 0.0 {
+            return Fragment { alpha: 0.0, ..self };
+        }
+        let color =
+            (self.color * self.alpha + back.color * back.alpha * (1.0 - self.alpha)) / alpha;
+        Fragment {
+            color,
+            alpha,
+            depth: self.depth,
+        }
+    }
+}
+
+pub fn blend_over(src: Vec3, alpha: f32, dst: u32) -> u32 {
+    let dst = from_argb8_v(dst);
+    to_argb8_v(255, src * alpha + dst * (1.0 - alpha))
+}
+
+// Per-pixel fixed-size fragment arrays.
+// Every pixel owns `layers` slots, the amount of layers is derived from the memory budget.
+// When a pixel runs out of slots the two farthest fragments are merged into one,
+// which is exact as long as they do not have to be reordered with later fragments.
+pub struct ABuffer {
+    width: usize,
+    height: usize,
+    layers: usize,
+    fragments: Vec<Fragment>,
+    counts: Vec<u8>,
+    overflowed: usize,
+    // bigger depth is closer, set by `Framebuffer::set_reverse_z`
+    pub reverse_z: bool,
+}
+
+impl ABuffer {
+    pub const MAX_LAYERS: usize = 32;
+
+    pub fn with_budget(width: usize, height: usize, budget_bytes: usize) -> Self {
+        let per_layer = width * height * std::mem::size_of::<Fragment>();
+        let layers = (budget_bytes / per_layer.max(1)).clamp(1, Self::MAX_LAYERS);
+
+        Self {
+            width,
+            height,
+            layers,
+            fragments: vec![Fragment::EMPTY; width * height * layers],
+            counts: vec![0; width * height],
+            overflowed: 0,
+            reverse_z: false,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn layers(&self) -> usize {
+        self.layers
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.fragments.len() * std::mem::size_of::<Fragment>() + self.counts.len()
+    }
+
+    // number of fragments merged by the overflow fallback since the last clear
+    pub fn overflowed(&self) -> usize {
+        self.overflowed
+    }
+
+    pub fn clear(&mut self) {
+        self.counts.iter_mut().for_each(|count| *count = 0);
+        self.overflowed = 0;
+    }
+
+    fn is_farther(reverse_z: bool, a: f32, b: f32) -> bool {
+        if reverse_z { a < b } else { a > b }
+    }
+
+    pub fn append(&mut self, index: usize, fragment: Fragment) {
+        let count = self.counts[index] as usize;
+        let slots = &mut self.fragments[index * self.layers..(index + 1) * self.layers];
+
+        if count < self.layers {
+            slots[count] = fragment;
+            self.counts[index] += 1;
+            return;
+        }
+
+        self.overflowed += 1;
+
+        // the two farthest of the stored fragments and the incoming one, `count` stands for the incoming
+        let reverse_z = self.reverse_z;
+        let depth_of = |i: usize| if i == count { fragment.depth } else { slots[i].depth };
+        let (mut farthest, mut second) = (count, usize::MAX);
+        for i in 0..count {
+            if Self::is_farther(reverse_z, depth_of(i), depth_of(farthest)) {
+                second = farthest;
+                farthest = i;
+            } else if second == usize::MAX || Self::is_farther(reverse_z, depth_of(i), depth_of(second)) {
+                second = i;
+            }
+        }
+
+        let fragment_at = |i: usize| if i == count { fragment } else { slots[i] };
+        let back = fragment_at(farthest);
+        let merged = Fragment { depth: back.depth, ..fragment_at(second).over(back) };
+
+        // the merged pair takes a slot of one of them, the incoming fragment the other one
+        match (farthest == count, second == count) {
+            (false, false) => {
+                slots[farthest] = merged;
+                slots[second] = fragment;
+            }
+            (true, _) => slots[second] = merged,
+            (_, true) => slots[farthest] = merged,
+        }
+    }
+
+    // Sorts the fragments of every pixel back to front and composites them over `color`.
+    // Fragments behind `depth`, the resolved depth buffer, were covered by opaque surfaces drawn after them.
+    pub fn resolve(&mut self, color: &mut [u32], depth: &[f32]) {
+        for (index, count) in self.counts.iter_mut().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+
+            let slots = &mut self.fragments[index * self.layers..index * self.layers + *count as usize];
+            if self.reverse_z {
+                slots.sort_unstable_by(|a, b| a.depth.total_cmp(&b.depth));
+            } else {
+                slots.sort_unstable_by(|a, b| b.depth.total_cmp(&a.depth));
+            }
+
+            let mut dst = from_argb8_v(color[index]);
+            for fragment in slots.iter().filter(|f| !Self::is_farther(self.reverse_z, f.depth, depth[index])) {
+                dst = fragment.color * fragment.alpha + dst * (1.0 - fragment.alpha);
+            }
+            color[index] = to_argb8_v(255, dst);
+
+            *count = 0;
+        }
+        self.overflowed = 0;
+    }
+}
@@ -1,8 +1,14 @@
 use std::path::Path;
 
 use glam::{/*Vec2, Vec3, */Mat4};
+use gltf::json::validation::Validate;
 
+use crate::error::{LoadError, Warnings};
 use crate::geometry::Mesh;
+use crate::material::Material;
+use crate::obj::load_obj;
+use crate::ply::load_ply;
+use crate::stl::load_stl;
 //clockwise
 // pub fn edge_function(v0: Vec2, v1: Vec2, p: Vec2) -> f32 {
 //     (p.x - v0.x) * (v1.y - v0.y) - (p.y - v0.y) * (v1.x - v0.x)
@@ -43,7 +49,7 @@ pub fn index_to_coords(p: usize, width: usize) -> (usize, usize) {
 }
 
 pub fn coords_to_index(x: usize, y: usize, width: usize) -> usize {
-    (x + y * width) as usize
+    x + y * width
 }
 
 pub fn to_argb8(a: u8, r: u8, g: u8, b: u8) -> u32 {
@@ -62,6 +68,39 @@ pub fn to_argb8_v(a: u8, c: glam::Vec3) -> u32 {
     argb
 }
 
+// inverse of `to_argb8_v`, channels are in 0..255 range
+pub fn from_argb8_v(argb: u32) -> glam::Vec3 {
+    glam::vec3(
+        ((argb >> 16) & 0xff) as f32,
+        ((argb >> 8) & 0xff) as f32,
+        (argb & 0xff) as f32,
+    )
+}
+
+// multiplies the color channels with `c`, keeps the alpha
+pub fn modulate_argb8(argb: u32, c: glam::Vec3) -> u32 {
+    let color = (from_argb8_v(argb) * c).clamp(glam::Vec3::ZERO, glam::Vec3::splat(255.0));
+    to_argb8_v((argb >> 24) as u8, color)
+}
+
+pub fn alpha_of_argb8(argb: u32) -> f32 {
+    (argb >> 24) as f32 / 255.0
+}
+
+// parses the first N arguments of a line of a text asset
+pub fn parse_floats<const N: usize>(args: &[&str], line: usize) -> Result<[f32; N], LoadError> {
+    if args.len() < N {
+        return Err(LoadError::parse(line, format!("expected {} numbers", N)));
+    }
+    let mut values = [0.0; N];
+    for (value, arg) in values.iter_mut().zip(args) {
+        *value = arg
+            .parse()
+            .map_err(|_| LoadError::parse(line, format!("invalid number '{}'", arg)))?;
+    }
+    Ok(values)
+}
+
 pub fn lerp<T>(start: T, end: T, alpha: f32) -> T
 where
     T: std::ops::Sub<Output = T>
@@ -110,10 +149,66 @@ pub fn cofactor(matrix: &Mat4) -> Mat4 {
     Mat4::from_cols_array(&dst)
 }
 
-pub fn load_gltf(path: &Path) -> Mesh {
-    // handle loading textures, cameras, meshes here
-    let (document, buffers, _images) = gltf::import(path).unwrap();
+// extensions the loader understands, other required ones are reported as errors
+pub const SUPPORTED_GLTF_EXTENSIONS: &[&str] = &[
+    "KHR_texture_transform",
+    "KHR_materials_emissive_strength",
+    "MSFT_lod",
+    "EXT_mesh_gpu_instancing",
+];
+
+// handles both `.gltf` and binary `.glb` files, whatever the extension says
+pub fn load_gltf(path: &Path) -> Result<(Mesh, Vec<Material>, Warnings), LoadError> {
+    let (document, buffers, materials, warnings) = import_gltf(path)?;
+    let mesh = first_mesh_node(&document)
+        .and_then(|node| node.mesh())
+        .map_or_else(Mesh::new, |mesh| Mesh::load_from_gltf(&mesh, &buffers, &materials));
+    Ok((mesh, materials, warnings))
+}
+
+// validated document with its buffers and materials
+pub fn import_gltf(path: &Path) -> Result<(gltf::Document, Vec<gltf::buffer::Data>, Vec<Material>, Warnings), LoadError> {
+    let (document, buffers) = import_gltf_buffers(path)?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut warnings = Warnings::new();
+    let materials: Vec<Material> = document
+        .materials()
+        .map(|material| Material::from_gltf(&material, directory, &buffers, &mut warnings))
+        .collect();
+    Ok((document, buffers, materials, warnings))
+}
+
+// validated document with its buffers, without loading the textures
+pub fn import_gltf_buffers(path: &Path) -> Result<(gltf::Document, Vec<gltf::buffer::Data>), LoadError> {
+    let gltf = gltf::Gltf::from_slice_without_validation(&std::fs::read(path)?)?;
+    let gltf::Gltf { document, blob } = gltf;
 
+    if let Some(extension) = document
+        .extensions_required()
+        .find(|e| !SUPPORTED_GLTF_EXTENSIONS.contains(e))
+    {
+        return Err(LoadError::Unsupported(format!("required glTF extension {}", extension)));
+    }
+    // the required extensions were checked above, gltf itself only knows the ones of its features
+    let json = document.clone().into_json();
+    let mut errors = Vec::new();
+    json.validate(&json, gltf::json::Path::new, &mut |path, error| {
+        let path = path();
+        if !path.as_str().starts_with("extensionsRequired") {
+            errors.push((path, error));
+        }
+    });
+    if !errors.is_empty() {
+        return Err(LoadError::Gltf(gltf::Error::Validation(errors)));
+    }
+
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let buffers = gltf::import_buffers(&document, Some(directory), blob)?;
+    Ok((document, buffers))
+}
+
+// the first node of the scenes with a mesh, the one the viewer shows
+pub fn first_mesh_node(document: &gltf::Document) -> Option<gltf::Node<'_>> {
     for scene in document.scenes() {
         for node in scene.nodes() {
             println!(
@@ -131,11 +226,28 @@ pub fn load_gltf(path: &Path) -> Mesh {
                 node.transform().decomposed().1,
                 node.transform().decomposed().2,
             );
-            if let Some(mesh) = node.mesh() {
-                return Mesh::load_from_gltf(&mesh, &buffers);
+            if node.mesh().is_some() {
+                return Some(node);
             }
         }
     }
+    None
+}
+
+// formats that come without texture coordinates
+pub fn is_untextured_format(path: &Path) -> bool {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    matches!(extension.as_str(), "stl" | "ply")
+}
 
-    Mesh::new()
-}
\ No newline at end of file
+// picks the loader from the file extension
+pub fn load_mesh(path: &Path) -> Result<(Mesh, Vec<Material>, Warnings), LoadError> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    match extension.as_str() {
+        "gltf" | "glb" => load_gltf(path),
+        "obj" => load_obj(path),
+        "stl" => Ok((load_stl(path)?, Vec::new(), Warnings::new())),
+        "ply" => Ok((load_ply(path)?, Vec::new(), Warnings::new())),
+        _ => Err(LoadError::Unsupported(format!("mesh format '{}'", extension))),
+    }
+}
@@ -0,0 +1,80 @@
+use glam::{Mat3, Mat4, UVec3, Vec3, Vec4, Vec4Swizzles};
+
+use crate::geometry::Vertex;
+use crate::raster::near_plane_distance;
+
+// sides of the clip volume a vertex is outside of
+const LEFT: u8 = 1;
+const RIGHT: u8 = 2;
+const BOTTOM: u8 = 4;
+const TOP: u8 = 8;
+// behind the near plane, the triangles using it have to be clipped
+const NEAR: u8 = 16;
+
+// Vertices of a draw run through the vertex stage once, however many triangles share them.
+// Triangles are then assembled from the indices of the mesh. Normals are taken to world space
+// for lighting.
+#[derive(Debug, Clone, Default)]
+pub struct PostTransform {
+    pub clip: Vec<Vec4>,
+    // divided by w, the position in NDC and the attributes ready for perspective correct interpolation,
+    // only meaningful in front of the near plane
+    pub projected: Vec<Vertex>,
+    // 1 / w
+    pub rec: Vec<f32>,
+    // world space, unit length
+    pub normals: Vec<Vec3>,
+    outcodes: Vec<u8>,
+}
+
+impl PostTransform {
+    pub fn new(vertices: &[Vertex], model: &Mat4, mvp: &Mat4, reverse_z: bool) -> Self {
+        let mut stage = Self {
+            clip: Vec::with_capacity(vertices.len()),
+            projected: Vec::with_capacity(vertices.len()),
+            rec: Vec::with_capacity(vertices.len()),
+            normals: Vec::with_capacity(vertices.len()),
+            outcodes: Vec::with_capacity(vertices.len()),
+        };
+        // inverse transpose, normals stay perpendicular under non-uniform scale
+        let normal_matrix = Mat3::from_mat4(*model).inverse().transpose();
+        for vertex in vertices {
+            let clip = *mvp * vertex.pos.extend(1.0);
+            let rec = 1.0 / clip.w;
+            let normal = (normal_matrix * vertex.normal).normalize_or_zero();
+            let mut projected = Vertex { normal, ..*vertex } * rec;
+            projected.pos = clip.xyz() * rec;
+
+            let mut outcode = 0;
+            if clip.x < -clip.w { outcode |= LEFT; }
+            if clip.x > clip.w { outcode |= RIGHT; }
+            if clip.y < -clip.w { outcode |= BOTTOM; }
+            if clip.y > clip.w { outcode |= TOP; }
+            if near_plane_distance(clip, reverse_z) < 0.0 { outcode |= NEAR; }
+
+            stage.clip.push(clip);
+            stage.projected.push(projected);
+            stage.rec.push(rec);
+            stage.normals.push(normal);
+            stage.outcodes.push(outcode);
+        }
+        stage
+    }
+
+    pub fn clip(&self, triangle: UVec3) -> [Vec4; 3] {
+        triangle.to_array().map(|i| self.clip[i as usize])
+    }
+
+    // The triangle is entirely on the outer side of one of the planes and can be skipped.
+    // The far plane is not tested, it is at infinity with reverse-Z.
+    pub fn is_outside(&self, triangle: UVec3) -> bool {
+        let [a, b, c] = triangle.to_array().map(|i| self.outcodes[i as usize]);
+        a & b & c != 0
+    }
+
+    // some vertex is behind the near plane, the projected vertices cannot be used
+    pub fn needs_clipping(&self, triangle: UVec3) -> bool {
+        let [a, b, c] = triangle.to_array().map(|i| self.outcodes[i as usize]);
+        (a | b | c) & NEAR != 0
+    }
+}